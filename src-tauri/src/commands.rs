@@ -0,0 +1,174 @@
+// Tauri commands: thin wrappers that expose the engine to the frontend.
+
+use crate::engine::{
+    self, ChangeCount, FilePreview, GroupedPreview, NameValidation, PlanFormat, RenameCommand,
+    RenameOptions, RenameResult, SkipReason, UndoResult, WidthMode,
+};
+
+#[tauri::command]
+pub fn handle_rename(
+    path: String,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> RenameResult {
+    engine::handle_rename(path, cmd, options)
+}
+
+#[tauri::command]
+pub fn handle_rename_bytes(
+    path: Vec<u8>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> RenameResult {
+    engine::handle_rename_bytes(path, cmd, options)
+}
+
+#[tauri::command]
+pub fn handle_rename_batch(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    engine::handle_rename_batch(paths, cmd, options)
+}
+
+#[tauri::command]
+pub fn retry_failed(
+    results: Vec<RenameResult>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    engine::retry_failed(results, cmd, options)
+}
+
+#[tauri::command]
+pub fn handle_rename_recursive(
+    dir: String,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+    max_depth: Option<usize>,
+) -> Vec<RenameResult> {
+    engine::handle_rename_recursive(dir, cmd, options, max_depth)
+}
+
+#[tauri::command]
+pub fn handle_rename_mixed(
+    ops: Vec<(String, RenameCommand)>,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    engine::handle_rename_mixed(ops, options)
+}
+
+#[tauri::command]
+pub fn apply_names(
+    pairs: Vec<(String, String)>,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    engine::apply_names(pairs, options)
+}
+
+#[tauri::command]
+pub fn preview_many(paths: Vec<String>, cmd: RenameCommand) -> Vec<(String, String)> {
+    engine::preview_many(paths, cmd)
+}
+
+#[tauri::command]
+pub fn count_matches(paths: Vec<String>, pattern: String) -> Result<Vec<(String, usize)>, String> {
+    engine::count_matches(paths, pattern)
+}
+
+#[tauri::command]
+pub fn apply_name_list(
+    paths: Vec<String>,
+    names_file: String,
+    keep_ext: bool,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    engine::apply_name_list(paths, names_file, keep_ext, options)
+}
+
+#[tauri::command]
+pub fn analyze_batch(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> Vec<SkipReason> {
+    engine::analyze_batch(paths, cmd, options)
+}
+
+#[tauri::command]
+pub fn list_with_preview(dir: String, cmd: RenameCommand, recursive: bool) -> Vec<FilePreview> {
+    engine::list_with_preview(dir, cmd, recursive)
+}
+
+#[tauri::command]
+pub fn count_changes(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> ChangeCount {
+    engine::count_changes(paths, cmd, options)
+}
+
+#[tauri::command]
+pub fn preview_grouped(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> GroupedPreview {
+    engine::preview_grouped(paths, cmd, options)
+}
+
+#[tauri::command]
+pub fn preview_by_risk(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> Vec<FilePreview> {
+    engine::preview_by_risk(paths, cmd, options)
+}
+
+#[tauri::command]
+pub fn export_plan(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+    format: PlanFormat,
+) -> String {
+    engine::export_plan(paths, cmd, options, format)
+}
+
+#[tauri::command]
+pub fn validate_name(name: String) -> NameValidation {
+    engine::validate_name(name)
+}
+
+#[tauri::command]
+pub fn convert_width(text: String, mode: WidthMode) -> String {
+    engine::convert_width(text, mode)
+}
+
+#[tauri::command]
+pub fn restore_from_journal(journal_path: String) -> Vec<RenameResult> {
+    engine::restore_from_journal(journal_path)
+}
+
+#[tauri::command]
+pub fn restore_from_sidecars(dir: String) -> Vec<RenameResult> {
+    engine::restore_from_sidecars(dir)
+}
+
+#[tauri::command]
+pub fn undo_last_batch(journal_path: String) -> UndoResult {
+    engine::undo_last_batch(journal_path)
+}
+
+#[tauri::command]
+pub fn undo_ops(journal_path: String, op_ids: Vec<String>) -> Vec<RenameResult> {
+    engine::undo_ops(journal_path, op_ids)
+}
+
+#[tauri::command]
+pub fn reset_counter(key: String, value: u64) -> Result<(), String> {
+    engine::reset_counter(key, value)
+}
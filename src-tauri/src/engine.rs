@@ -0,0 +1,5141 @@
+// The renaming engine: name computation, validation and the filesystem side
+// of renames, batches, previews and undo. Usable without the `tauri` feature.
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+mod batch;
+mod counters;
+mod journal;
+mod naming;
+
+pub use batch::*;
+pub use counters::*;
+pub use journal::*;
+pub use naming::*;
+
+// --- Enum types for type-safe deserialization ---
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    Start,
+    End,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CaseMode {
+    Upper,
+    Lower,
+}
+
+/// Which stems `Case` touches, so names that mix case on purpose (`iPhone`)
+/// can be left alone. Characters without case don't count either way.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseCondition {
+    /// No uppercase letters in the stem.
+    AllLower,
+    /// No lowercase letters in the stem.
+    AllUpper,
+    #[default]
+    Always,
+}
+
+impl CaseCondition {
+    fn holds(self, stem: &str) -> bool {
+        match self {
+            CaseCondition::AllLower => !stem.chars().any(char::is_uppercase),
+            CaseCondition::AllUpper => !stem.chars().any(char::is_lowercase),
+            CaseCondition::Always => true,
+        }
+    }
+}
+
+/// Which side of the marker `TrimRelative` keeps.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MarkerSide {
+    Before,
+    After,
+}
+
+/// Which side of a serial number `pad` fills. `Right` is for fixed-field
+/// names where `5` at width 4 must read `5000`; it changes the number's value
+/// as read back, so only use it when the consumer expects that layout.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PadSide {
+    #[default]
+    Left,
+    Right,
+}
+
+/// When a serial number gets a sign in front of its padded magnitude, for
+/// datasets that encode offsets (`-03`, `+05`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SignMode {
+    /// Only negative numbers: `-03`, `00`, `05`.
+    #[default]
+    Negative,
+    /// Non-zero numbers: `-03`, `00`, `+05`.
+    Always,
+    /// Zero counts as positive: `-03`, `+00`, `+05`.
+    AlwaysWithZero,
+}
+
+/// Base a serial number is written in. Hex digits above 9 come in the case
+/// the variant names.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Radix {
+    #[default]
+    Decimal,
+    HexUpper,
+    HexLower,
+    Octal,
+}
+
+impl Radix {
+    fn digits(self, n: u64) -> String {
+        match self {
+            Radix::Decimal => n.to_string(),
+            Radix::HexUpper => format!("{:X}", n),
+            Radix::HexLower => format!("{:x}", n),
+            Radix::Octal => format!("{:o}", n),
+        }
+    }
+}
+
+/// Where `Case` and `Convert` split a name such as `my.config.json` into the
+/// part they edit and the extension they keep: `LastDot` edits `my.config`,
+/// `FirstDot` edits only `my`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtSplit {
+    FirstDot,
+    #[default]
+    LastDot,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum WidthMode {
+    Zenkaku,
+    Hankaku,
+}
+
+/// Unicode compatibility normalization forms.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CompatibilityForm {
+    Nfkc,
+    Nfkd,
+}
+
+/// Chinese script for `HanConvert`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum HanScript {
+    Simplified,
+    Traditional,
+}
+
+/// Most bytes content hashing reads from one file unless told otherwise.
+const DEFAULT_HASH_MAX_BYTES: u64 = 4 << 30;
+
+/// How long content hashing waits on one file unless told otherwise.
+const DEFAULT_HASH_TIMEOUT_MS: u64 = 60_000;
+
+fn default_hash_max_bytes() -> Option<u64> {
+    Some(DEFAULT_HASH_MAX_BYTES)
+}
+
+fn default_hash_timeout_ms() -> Option<u64> {
+    Some(DEFAULT_HASH_TIMEOUT_MS)
+}
+
+/// Digests available to `AddChecksum`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgo {
+    Crc32,
+    Sha1,
+}
+
+/// Which column of an abbreviation dictionary is replaced by the other.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum AbbrevDirection {
+    /// `Dr` → `Doctor`
+    Expand,
+    /// `Doctor` → `Dr`
+    Contract,
+}
+
+// --- Rename commands ---
+
+fn default_pad_char() -> char {
+    '0'
+}
+
+/// One rename operation, sent by the frontend as `{mode, config}`.
+///
+/// Commands that normalize a name (`Case`, `Convert`, `Slugify`, the
+/// `Strip*` family, ...) are idempotent: run on their own output they report
+/// `Unchanged`. Commands that add to the name (`Add`, `Wrap`, `AddChecksum`,
+/// `AddDimensions`, `FolderIndex`, `Serial` with `keep_original`) or shift a
+/// number (`ShiftNumber`) change it again on every run.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "mode", content = "config")]
+pub enum RenameCommand {
+    Fixed {
+        name: String,
+        keep_ext: bool,
+        /// Treat `name` as a template and expand `{date}`-style tokens in it.
+        /// Off, the name is used as given, braces and all.
+        #[serde(default)]
+        expand_tokens: bool,
+    },
+    Serial {
+        prefix: String,
+        suffix: String,
+        /// First number; may be negative.
+        number: i64,
+        /// Minimum width of the number's magnitude; a sign comes on top.
+        pad: usize,
+        keep_ext: bool,
+        keep_original: bool,
+        #[serde(default)]
+        pad_side: PadSide,
+        /// Fill character for either side; `'0'` unless given.
+        #[serde(default = "default_pad_char")]
+        pad_char: char,
+        #[serde(default)]
+        sign: SignMode,
+        /// Base of the number; `pad` counts digits in this base.
+        #[serde(default)]
+        radix: Radix,
+    },
+    Replace {
+        from: String,
+        to: String,
+        use_regex: bool,
+        // Regex-only flags. These set the defaults for the whole pattern;
+        // inline flags such as `(?-i)` still override them where they appear.
+        #[serde(default)]
+        ignore_case: bool,
+        #[serde(default)]
+        multi_line: bool,
+        #[serde(default)]
+        dot_matches_new_line: bool,
+        /// Match against the whole name, then put the original extension
+        /// back on whatever the replacement left as its stem, so a pattern
+        /// reaching across the dot can't change the extension.
+        #[serde(default)]
+        preserve_ext: bool,
+        /// Regex only: insert `to` verbatim, so `$3.50` isn't read as a
+        /// reference to group 3.
+        #[serde(default)]
+        literal_replacement: bool,
+        /// Literal only: match `from` in any case and give `to` the case of
+        /// each match, so `color` -> `colour` also turns `COLOR` into
+        /// `COLOUR` and `Color` into `Colour`.
+        #[serde(default)]
+        preserve_case: bool,
+    },
+    /// With `repad_trailing` the number ending the original stem is
+    /// re-padded to that width before `text` is added, so `ep5` with an
+    /// `_old` suffix and width 3 becomes `ep005_old`.
+    Add {
+        text: String,
+        position: Position,
+        #[serde(default)]
+        repad_trailing: Option<usize>,
+    },
+    Trim {
+        count: usize,
+        position: Position,
+    },
+    /// Keep only the part of the stem before or after the first `marker`.
+    TrimRelative {
+        marker: String,
+        keep: MarkerSide,
+        include_marker: bool,
+    },
+    TrimBoth {
+        start: usize,
+        end: usize,
+    },
+    Extension {
+        new_ext: String,
+    },
+    EnsureExt {
+        ext: String,
+    },
+    /// Drop copies of the extension doubled onto the stem, in any case:
+    /// `report.pdf.pdf` becomes `report.pdf`. Only the final extension is
+    /// compared, so `archive.tar.tar.gz` and `a.txt.bak` stay as they are.
+    DedupeExtension,
+    Case {
+        mode: CaseMode,
+        #[serde(default)]
+        split: ExtSplit,
+        /// Stems failing the condition are reported `Unchanged`.
+        #[serde(default)]
+        only_if: CaseCondition,
+        /// Recase the extension too: `Photo.JPG` -> `photo.jpg`.
+        #[serde(default)]
+        include_ext: bool,
+    },
+    CapitalizeAfter {
+        delimiters: Vec<char>,
+    },
+    Convert {
+        mode: WidthMode,
+        #[serde(default)]
+        split: ExtSplit,
+    },
+    Compatibility {
+        form: CompatibilityForm,
+    },
+    JaPunctuation {
+        to: WidthMode,
+    },
+    /// Convert Chinese in the stem between simplified and traditional
+    /// script with the OpenCC tables, phrases first so one-to-many characters
+    /// pick the right form (`头发` -> `頭髮`, not `頭發`).
+    HanConvert {
+        to: HanScript,
+    },
+    Wrap {
+        open: String,
+        close: String,
+    },
+    GroupDigits {
+        separator: String,
+        strip: Vec<char>,
+    },
+    MoveNumber {
+        to: Position,
+    },
+    ExifDate {
+        format: String,
+        keep_ext: bool,
+        #[serde(default)]
+        fallback_to_mtime: bool,
+    },
+    /// Stem from audio tags: `{artist}`, `{album}`, `{title}`, `{track}` and
+    /// `{year}` in `pattern`, keeping the extension. Missing tags expand to
+    /// `placeholder`.
+    MediaTags {
+        pattern: String,
+        #[serde(default)]
+        placeholder: String,
+    },
+    /// Insert the image's `WxH` into the stem next to `separator`.
+    AddDimensions {
+        separator: String,
+        position: Position,
+    },
+    CollapseRepeats {
+        chars: Vec<char>,
+    },
+    /// `max_bytes` and `timeout_ms` bound the read so one huge or stalled
+    /// file fails with `FileTooLarge` / `ReadTimeout` instead of holding up
+    /// the batch. Left out they default to 4 GiB and 60 s; `null` lifts the
+    /// limit.
+    AddChecksum {
+        algo: ChecksumAlgo,
+        length: usize,
+        #[serde(default = "default_hash_max_bytes")]
+        max_bytes: Option<u64>,
+        #[serde(default = "default_hash_timeout_ms")]
+        timeout_ms: Option<u64>,
+    },
+    StripControlChars {
+        #[serde(default)]
+        replacement: String,
+    },
+    /// Remove `words` from the stem along with the separators they leave
+    /// dangling (`The Matrix` -> `Matrix`). With `whole_word` only complete
+    /// words between separators (space, `_`, `-`, `.`) match.
+    RemoveWords {
+        words: Vec<String>,
+        whole_word: bool,
+        ignore_case: bool,
+    },
+    /// Remove bidirectional formatting characters (marks, embeddings,
+    /// overrides, isolates) from the stem.
+    StripBidi,
+    FolderIndex {
+        pad: usize,
+        position: Position,
+    },
+    Abbreviations {
+        dictionary: String,
+        direction: AbbrevDirection,
+    },
+    ShiftNumber {
+        delta: i64,
+        pad: usize,
+    },
+    /// Stem from the file's path below `root`, directories included:
+    /// `root/a/b/img.jpg` becomes `a__b__img.jpg` with `__`. Meant for
+    /// `handle_rename_recursive`; the file stays in its own folder.
+    FlattenName {
+        root: String,
+        separator: String,
+    },
+    /// Batch: zero-pad each file's first number to the widest first number
+    /// in the batch, leaving values alone (`2`, `15`, `100` -> `002`, `015`,
+    /// `100`).
+    AlignNumbers,
+    /// Batch: shift every file's first number by the same amount so the
+    /// smallest becomes `new_start`, keeping the gaps and each number's
+    /// zero padding (`1`, `5`, `9` with 10 -> `10`, `14`, `18`).
+    RebaseNumbers {
+        new_start: u32,
+    },
+    Counter {
+        key: String,
+        prefix: String,
+        suffix: String,
+        pad: usize,
+        keep_ext: bool,
+    },
+    TrimWhitespace {
+        start: bool,
+        end: bool,
+    },
+    /// Put `separator` between every letter and digit that touch in the
+    /// stem, either way round: `abc123def` becomes `abc_123_def`.
+    SeparateAlnum {
+        separator: String,
+    },
+    /// Strip every leading (`start`) and trailing (`end`) character of the
+    /// stem found in `chars`: `__report_` becomes `report`.
+    TrimSeparators {
+        chars: Vec<char>,
+        start: bool,
+        end: bool,
+    },
+    TimeStamp {
+        format: String,
+        use_file_time: bool,
+        ensure_unique: bool,
+    },
+    ReformatDate {
+        from: String,
+        to: String,
+    },
+    StripEmoji {
+        #[serde(default)]
+        replacement: String,
+    },
+    Slugify {
+        separator: String,
+        #[serde(default)]
+        max_length: Option<usize>,
+    },
+    /// Recase the extension of files whose extension (any case) is listed;
+    /// the rest are left `Unchanged`.
+    ExtCaseFor {
+        extensions: Vec<String>,
+        mode: CaseMode,
+    },
+    SwapExtension {
+        from: String,
+        to: String,
+    },
+    RegexSortSerial {
+        pattern: String,
+        key_group: usize,
+        prefix: String,
+        pad: usize,
+    },
+    /// Batch: number the files from 1 by modification time, oldest first,
+    /// whatever their current names. Equal times fall back to the name.
+    TimeOrderSerial {
+        prefix: String,
+        pad: usize,
+        keep_ext: bool,
+    },
+    /// Move the file, name unchanged, into a subfolder of its directory named
+    /// by `folder` with `pattern`'s captures filled in (`$1`, `${year}`); the
+    /// folder is created if needed. `/` in `folder` nests subfolders.
+    SortInto {
+        pattern: String,
+        folder: String,
+    },
+    /// Name returned by a Rhai script that sees `name`, `stem`, `ext`,
+    /// `index` and `parent`, e.g. `stem.to_upper() + "." + ext`. Scripts
+    /// can't reach files or the network and are stopped after
+    /// [`SCRIPT_MAX_OPERATIONS`] steps.
+    Script {
+        source: String,
+    },
+    /// Give the file the stem of its sibling with `reference_ext` whose stem
+    /// contains this one as a whole token, keeping pairs in sync:
+    /// `IMG_001.raw` next to `IMG_001 beach.jpg` becomes `IMG_001 beach.raw`.
+    MatchSibling {
+        reference_ext: String,
+    },
+}
+
+/// Machine-readable outcome of a rename. `status` carries the human-readable
+/// message; the frontend should branch on `code` instead of parsing it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultCode {
+    Success,
+    Unchanged,
+    NotFound,
+    InvalidPath,
+    InvalidFilename,
+    InvalidInput,
+    EmptyName,
+    InvalidName,
+    TrimTooLong,
+    NoNumberFound,
+    NoExifDate,
+    NoTags,
+    NotAnImage,
+    NoReference,
+    NoDateMatched,
+    NoMatch,
+    FilteredOut,
+    BlockedBySafeMode,
+    Locked,
+    TargetExists,
+    DuplicateTarget,
+    NameListMismatch,
+    PreconditionFailed,
+    /// Would have been renamed, but a transactional batch was abandoned
+    /// because another entry would fail.
+    Aborted,
+    NothingToUndo,
+    FileTooLarge,
+    ReadTimeout,
+    IoError,
+}
+
+/// Details for [`ResultCode::TrimTooLong`]: the requested count and the stem
+/// length it exceeded. The largest allowed count is `len - 1`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrimOverflow {
+    count: usize,
+    len: usize,
+}
+
+/// Old and new name split around the changed part, so the UI can highlight
+/// `removed`/`added` between the shared `prefix` and `suffix`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NameDiff {
+    prefix: String,
+    removed: String,
+    added: String,
+    suffix: String,
+}
+
+impl NameDiff {
+    fn between(old: &str, new: &str) -> Self {
+        let old_chars: Vec<char> = old.chars().collect();
+        let new_chars: Vec<char> = new.chars().collect();
+
+        let prefix_len = old_chars
+            .iter()
+            .zip(&new_chars)
+            .take_while(|(a, b)| a == b)
+            .count();
+        // The suffix must not overlap the prefix in either name
+        let max_suffix = old_chars.len().min(new_chars.len()) - prefix_len;
+        let suffix_len = old_chars
+            .iter()
+            .rev()
+            .zip(new_chars.iter().rev())
+            .take(max_suffix)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        NameDiff {
+            prefix: old_chars[..prefix_len].iter().collect(),
+            removed: old_chars[prefix_len..old_chars.len() - suffix_len]
+                .iter()
+                .collect(),
+            added: new_chars[prefix_len..new_chars.len() - suffix_len]
+                .iter()
+                .collect(),
+            suffix: old_chars[old_chars.len() - suffix_len..].iter().collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RenameResult {
+    path: String,
+    code: ResultCode,
+    status: String,
+    new_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trim_overflow: Option<TrimOverflow>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<NameDiff>,
+    /// Raw OS bytes of `path` / `new_name` when they aren't valid UTF-8;
+    /// the string fields then hold a lossy rendering for display.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path_bytes: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_name_bytes: Option<Vec<u8>>,
+    /// Problem noticed after an otherwise successful rename.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+    /// File size read before the rename, only with `RenameOptions::include_size`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size_bytes: Option<u64>,
+    /// Inode and device of the directory entry, only with
+    /// `RenameOptions::include_inode` on Unix. Equal pairs are hardlinks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inode: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<u64>,
+    /// Ids of a journaled rename: `batch_id` for `undo_last_batch`, `op_id`
+    /// for undoing just this file with `undo_ops`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    op_id: Option<String>,
+}
+
+impl RenameResult {
+    fn new(path: String, code: ResultCode, status: impl Into<String>) -> Self {
+        RenameResult {
+            path,
+            code,
+            status: status.into(),
+            new_name: None,
+            trim_overflow: None,
+            diff: None,
+            path_bytes: None,
+            new_name_bytes: None,
+            warning: None,
+            size_bytes: None,
+            inode: None,
+            device: None,
+            batch_id: None,
+            op_id: None,
+        }
+    }
+
+    fn success(path: String, new_name: String, diff: NameDiff) -> Self {
+        RenameResult {
+            new_name: Some(new_name),
+            diff: Some(diff),
+            ..RenameResult::new(path, ResultCode::Success, "Success")
+        }
+    }
+
+    fn failure(path: String, code: ResultCode, status: impl Into<String>) -> Self {
+        RenameResult::new(path, code, status)
+    }
+
+    fn from_error(path: String, e: NameError) -> Self {
+        RenameResult {
+            trim_overflow: e.trim_overflow,
+            ..RenameResult::new(path, e.code, e.message)
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn code(&self) -> ResultCode {
+        self.code
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    pub fn new_name(&self) -> Option<&str> {
+        self.new_name.as_deref()
+    }
+
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
+
+    /// Set `warning`, joining it to one already there with `; `.
+    fn add_warning(&mut self, note: String) {
+        self.warning = Some(match self.warning.take() {
+            Some(warning) => format!("{}; {}", warning, note),
+            None => note,
+        });
+    }
+}
+
+// --- Rename options ---
+
+/// Metadata test a file must pass to be renamed. Times are Unix seconds and
+/// compare against the modification time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Precondition {
+    MinSize(u64),
+    MaxSize(u64),
+    OlderThan(i64),
+    NewerThan(i64),
+}
+
+impl Precondition {
+    fn holds(&self, meta: &fs::Metadata) -> bool {
+        let mtime = || {
+            meta.modified()
+                .ok()
+                .map(|t| DateTime::<Utc>::from(t).timestamp())
+        };
+        match *self {
+            Precondition::MinSize(min) => meta.len() >= min,
+            Precondition::MaxSize(max) => meta.len() <= max,
+            Precondition::OlderThan(t) => mtime().is_some_and(|m| m < t),
+            Precondition::NewerThan(t) => mtime().is_some_and(|m| m > t),
+        }
+    }
+}
+
+/// What to do when the target name is already taken on disk.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Collision {
+    /// Report `TargetExists` and leave the file alone.
+    #[default]
+    Fail,
+    /// Try `template` before the extension with `{n}` = 1, 2, ... until a
+    /// free name turns up: `report.txt` -> `report (1).txt`.
+    Suffix {
+        #[serde(default = "default_suffix_template")]
+        template: String,
+    },
+}
+
+fn default_suffix_template() -> String {
+    " ({n})".to_string()
+}
+
+/// Token in `Collision::Suffix` templates for the attempt number.
+const SUFFIX_COUNTER_TOKEN: &str = "{n}";
+
+/// Highest `{n}` tried before giving up with `TargetExists`.
+const MAX_SUFFIX_ATTEMPTS: u32 = 9999;
+
+/// What a batch does with files that would get the same name.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupStrategy {
+    /// The first file gets the name; the rest fail with `DuplicateTarget`.
+    #[default]
+    Fail,
+    /// The rest get ` (2)`, ` (3)`, ... before the extension.
+    Numeric,
+    /// Each gets ` (<part>)` before the extension, `part` being the nearest
+    /// component of its source path that differs across the colliding files:
+    /// the old stem, then the old name, then each folder outward. Files
+    /// nothing tells apart fail as with `Fail`.
+    PathComponent,
+}
+
+/// Optional behavior shared by the rename commands. Every field defaults to off,
+/// so the frontend only sends what it needs.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct RenameOptions {
+    /// Append every successful rename to this JSON-lines journal so it can be
+    /// reversed later with `restore_from_journal`.
+    pub journal_path: Option<String>,
+    /// Only rename files whose name matches this regex; the rest are reported
+    /// as `FilteredOut`.
+    pub name_filter: Option<String>,
+    /// Report each file's size in `RenameResult::size_bytes`. Off by default
+    /// because it costs an extra stat per file.
+    pub include_size: bool,
+    /// Report `RenameResult::inode` / `device` so hardlinks can be spotted.
+    /// Unix only: std exposes no stable file ID on Windows, so both stay `None`.
+    pub include_inode: bool,
+    /// Refuse renames that look like mistakes; see [`safe_mode_violation`].
+    pub safe_mode: bool,
+    /// Report files held open or locked by another program as `Locked`
+    /// instead of a generic `IoError`, so they can be retried on their own.
+    pub skip_locked: bool,
+    /// Batch only: treat targets differing only by case as distinct even when
+    /// the filesystem is case-insensitive.
+    pub case_sensitive_conflicts: bool,
+    /// Batch only: rename from the last entry to the first. Use it when an
+    /// entry's target is a later entry's current name, e.g. `img1`→`img2`,
+    /// `img2`→`img3`: going backwards frees each target before it's needed.
+    /// Results stay in input order. It can't break a cycle (`a`↔`b`); that
+    /// needs a two-phase rename through temporary names.
+    pub reverse_order: bool,
+    /// Batch only: check the whole batch first, in the order it would run,
+    /// and rename nothing unless every entry would succeed. The failing
+    /// entries report why; the rest come back `Aborted` with the name they
+    /// would have had.
+    pub transactional: bool,
+    /// Batch only: hash the files (only those sharing a size with another)
+    /// and warn `Duplicate content of <path>` on each one whose content
+    /// matches another file in the batch. Renaming goes ahead regardless.
+    /// Files over 4 GiB or stalled for 60 s are not compared.
+    pub flag_duplicate_content: bool,
+    /// Handling of targets that already exist on disk. Duplicates within one
+    /// batch are handled by `dedup_strategy`.
+    pub collision: Collision,
+    /// Batch only: handling of several files planned to get the same name.
+    pub dedup_strategy: DedupStrategy,
+    /// Only rename files whose metadata passes this test; the rest are
+    /// reported as `PreconditionFailed`.
+    pub precondition: Option<Precondition>,
+    /// After each rename, write the original name to `<new name>.origname`
+    /// next to the file, for `restore_from_sidecars`. Unlike the journal it
+    /// travels with the folder.
+    pub write_sidecar: bool,
+    /// Flush the directory after each rename so the change survives a crash
+    /// before success is reported. A failed flush leaves a warning.
+    pub durable: bool,
+}
+
+/// [`RenameOptions`] with its regexes compiled, so a batch compiles them once.
+struct PreparedOptions<'a> {
+    options: &'a RenameOptions,
+    name_filter: Option<Regex>,
+    /// Tags this call's journal entries for `undo_last_batch`.
+    batch_id: String,
+    /// Journal entries written so far in this call, for numbering op ids.
+    ops_written: Cell<usize>,
+}
+
+impl<'a> PreparedOptions<'a> {
+    fn new(options: &'a RenameOptions) -> Result<Self, NameError> {
+        let name_filter = match &options.name_filter {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| {
+                NameError::new(
+                    ResultCode::InvalidInput,
+                    format!("Name filter error: {}", e),
+                )
+            })?),
+            None => None,
+        };
+        if let Collision::Suffix { template } = &options.collision {
+            check_suffix_template(template)?;
+        }
+        Ok(PreparedOptions {
+            options,
+            name_filter,
+            batch_id: new_batch_id(),
+            ops_written: Cell::new(0),
+        })
+    }
+}
+
+/// A suffix template must number its attempts and, filled in, be legal
+/// in a name.
+fn check_suffix_template(template: &str) -> Result<(), NameError> {
+    if !template.contains(SUFFIX_COUNTER_TOKEN) {
+        return Err(NameError::new(
+            ResultCode::InvalidInput,
+            "Suffix template must contain {n}",
+        ));
+    }
+    let sample = template.replace(SUFFIX_COUNTER_TOKEN, "1");
+    let illegal = check_name(&sample, cfg!(windows)).illegal_chars;
+    if !illegal.is_empty() {
+        let chars: String = illegal.iter().collect();
+        return Err(NameError::new(
+            ResultCode::InvalidInput,
+            format!("Illegal characters in suffix template: {:?}", chars),
+        ));
+    }
+    Ok(())
+}
+
+/// `name` with `template` (its `{n}` replaced by `n`) inserted before the
+/// extension.
+fn suffixed_name(name: &OsStr, template: &str, n: u32) -> OsString {
+    let path = Path::new(name);
+    let suffix = template.replace(SUFFIX_COUNTER_TOKEN, &n.to_string());
+    let stem = path.file_stem().unwrap_or(name);
+    match path.extension() {
+        Some(ext) => concat_os(&[stem, OsStr::new(&suffix), OsStr::new("."), ext]),
+        None => concat_os(&[stem, OsStr::new(&suffix)]),
+    }
+}
+
+// --- Filesystem helpers ---
+
+/// Entries of a folder looked at by [`is_case_insensitive`] before it gives up.
+const CASE_PROBE_ENTRIES: usize = 64;
+
+/// Whether `dir` lives on a case-insensitive filesystem, found without
+/// writing anything: an existing name with cased letters (an entry of `dir`,
+/// or `dir`'s own name) is looked up with its case swapped. `dir` may not
+/// exist yet (a `SortInto` folder); its nearest existing ancestor is probed.
+/// With nothing to look up, assumes the platform default.
+fn is_case_insensitive(dir: &Path) -> bool {
+    let Some(existing) = dir.ancestors().find(|d| d.is_dir()) else {
+        return cfg!(any(windows, target_os = "macos"));
+    };
+    let entries = fs::read_dir(existing)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .take(CASE_PROBE_ENTRIES)
+        .map(|entry| entry.path());
+    let own = existing.parent().map(|_| existing.to_path_buf());
+    for path in entries.chain(own) {
+        let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+            continue;
+        };
+        let upper = name.to_uppercase();
+        let swapped = if upper != name {
+            upper
+        } else {
+            name.to_lowercase()
+        };
+        if swapped != name {
+            return same_entry(&path, &path.with_file_name(swapped));
+        }
+    }
+    cfg!(any(windows, target_os = "macos"))
+}
+
+/// Whether `a` and `b` name the same directory entry.
+#[cfg(unix)]
+fn same_entry(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+        _ => false,
+    }
+}
+
+/// Whether `a` and `b` name the same directory entry. Only called with
+/// names differing in case, which can't both exist on Windows' filesystems.
+#[cfg(not(unix))]
+fn same_entry(_a: &Path, b: &Path) -> bool {
+    fs::symlink_metadata(b).is_ok()
+}
+
+/// Rename `from` to `to`, failing with `AlreadyExists` instead of replacing an
+/// existing target.
+///
+/// On Linux the check is enforced by the kernel via `renameat2` with
+/// `RENAME_NOREPLACE`, so no file can slip in between the check and the rename.
+/// Filesystems that don't support the flag fall back to [`rename_checked`].
+#[cfg(target_os = "linux")]
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let from_c = CString::new(from.as_os_str().as_bytes())?;
+    let to_c = CString::new(to.as_os_str().as_bytes())?;
+
+    // SAFETY: both pointers come from live CStrings and AT_FDCWD resolves
+    // relative paths against the current directory, like fs::rename does.
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from_c.as_ptr(),
+            libc::AT_FDCWD,
+            to_c.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if ret == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        // Old kernel, or a filesystem (e.g. some network mounts) without RENAME_NOREPLACE
+        Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => {
+            rename_checked(from, to)
+        }
+        _ => Err(err),
+    }
+}
+
+/// Whether `e` means another process has the file open or locked: a sharing
+/// or lock violation on Windows, `EBUSY`/`ETXTBSY` elsewhere.
+fn is_lock_error(e: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    const WINDOWS_LOCK_CODES: &[i32] = &[32, 33];
+
+    if cfg!(windows) {
+        e.raw_os_error()
+            .is_some_and(|code| WINDOWS_LOCK_CODES.contains(&code))
+    } else {
+        matches!(
+            e.kind(),
+            io::ErrorKind::ResourceBusy | io::ErrorKind::ExecutableFileBusy
+        )
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    rename_checked(from, to)
+}
+
+/// Longest path the classic Windows API accepts (`MAX_PATH`, 260, minus the
+/// terminating NUL).
+const WINDOWS_MAX_PATH: usize = 259;
+
+/// `path` in extended-length form (`\\?\C:\...`, `\\?\UNC\server\...`)
+/// when it's absolute and too long for the classic API, which then fails
+/// even if every name in it is fine. `None` when it can be used as is. The
+/// prefix turns off path normalization, so `/` becomes `\` here.
+fn extended_length_path(path: &str) -> Option<String> {
+    if path.encode_utf16().count() <= WINDOWS_MAX_PATH || path.starts_with(r"\\?\") {
+        return None;
+    }
+    let path = path.replace('/', r"\");
+    let bytes = path.as_bytes();
+    let is_drive_absolute =
+        bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'\\';
+    if let Some(unc) = path.strip_prefix(r"\\") {
+        Some(format!(r"\\?\UNC\{}", unc))
+    } else if is_drive_absolute {
+        Some(format!(r"\\?\{}", path))
+    } else {
+        None
+    }
+}
+
+/// `path` as handed to the OS: extended-length on Windows when needed,
+/// untouched elsewhere.
+fn os_path(path: &Path) -> std::borrow::Cow<'_, Path> {
+    match path
+        .to_str()
+        .filter(|_| cfg!(windows))
+        .and_then(extended_length_path)
+    {
+        Some(long) => std::borrow::Cow::Owned(PathBuf::from(long)),
+        None => std::borrow::Cow::Borrowed(path),
+    }
+}
+
+/// Flush `dir`'s entries to disk, making a finished rename in it durable.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// No-op: Windows can't open a directory for flushing, and NTFS journals
+/// the rename itself.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Check-then-rename fallback. Leaves a small race window between the
+/// existence check and the rename, so prefer [`rename_noreplace`].
+fn rename_checked(from: &Path, to: &Path) -> io::Result<()> {
+    let (from, to) = (os_path(from), os_path(to));
+    if to.exists() {
+        return Err(io::ErrorKind::AlreadyExists.into());
+    }
+    fs::rename(from, to)
+}
+
+// --- Errors ---
+
+/// Why no new name could be computed for a file.
+#[derive(Debug, Clone)]
+pub struct NameError {
+    code: ResultCode,
+    message: String,
+    trim_overflow: Option<TrimOverflow>,
+}
+
+impl NameError {
+    fn new(code: ResultCode, message: impl Into<String>) -> Self {
+        NameError {
+            code,
+            message: message.into(),
+            trim_overflow: None,
+        }
+    }
+
+    pub fn code(&self) -> ResultCode {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for NameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for NameError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, NaiveDate};
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+    use unicode_normalization::UnicodeNormalization;
+
+    #[test]
+    fn test_rename_fixed() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Fixed {
+            name: "new_name".into(),
+            keep_ext: true,
+            expand_tokens: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "new_name.txt");
+    }
+
+    #[test]
+    fn test_rename_serial_suffix() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("test.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Serial {
+            prefix: "".into(),
+            suffix: "_suffix".into(),
+            number: 1,
+            pad: 3,
+            keep_ext: true,
+            keep_original: false,
+            pad_side: PadSide::Left,
+            pad_char: '0',
+            sign: SignMode::Negative,
+            radix: Radix::Decimal,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "001_suffix.txt");
+    }
+
+    #[test]
+    fn test_rename_trim_end() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("abcde.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Trim {
+            count: 2,
+            position: Position::End,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "abc.txt");
+    }
+
+    #[test]
+    fn test_rename_replace() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("old_name_v1.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Replace {
+            from: "v1".into(),
+            to: "v2".into(),
+            use_regex: false,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "old_name_v2.txt");
+    }
+
+    #[test]
+    fn test_rename_replace_regex() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("image_123_test.png");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Replace {
+            from: r"(\d+)".into(),
+            to: "NUM".into(),
+            use_regex: true,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "image_NUM_test.png");
+    }
+
+    #[test]
+    fn test_rename_add_start() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("file.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Add {
+            text: "prefix_".into(),
+            position: Position::Start,
+            repad_trailing: None,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "prefix_file.txt");
+    }
+
+    #[test]
+    fn test_rename_extension() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("photo.jpg");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Extension {
+            new_ext: "png".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "photo.png");
+    }
+
+    #[test]
+    fn test_rename_target_exists() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("a.txt");
+        File::create(&file_path).unwrap();
+        File::create(dir.path().join("b.txt")).unwrap();
+
+        let cmd = RenameCommand::Fixed {
+            name: "b".into(),
+            keep_ext: true,
+            expand_tokens: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Target exists: b.txt");
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_rename_noreplace_refuses_existing() {
+        let dir = tempdir().unwrap();
+        let from = dir.path().join("a.txt");
+        let to = dir.path().join("b.txt");
+        fs::write(&from, "a").unwrap();
+        fs::write(&to, "b").unwrap();
+
+        let err = rename_noreplace(&from, &to).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&to).unwrap(), "b");
+        assert!(from.exists());
+    }
+
+    #[test]
+    fn test_rename_ensure_ext_appends_missing() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("report.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::EnsureExt { ext: ".bak".into() };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "report.txt.bak");
+    }
+
+    #[test]
+    fn test_rename_ensure_ext_already_suffixed() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("report.txt.BAK");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::EnsureExt { ext: "bak".into() };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Unchanged");
+        assert!(res.new_name.is_none());
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_rename_capitalize_after() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("hello-world_test track.mp3");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::CapitalizeAfter {
+            delimiters: vec![' ', '-'],
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "Hello-World_test Track.mp3");
+    }
+
+    #[test]
+    fn test_capitalize_after_keeps_other_letters() {
+        assert_eq!(
+            capitalize_after("hello-world_test", &[' ', '-']),
+            "Hello-World_test"
+        );
+        assert_eq!(capitalize_after("mIxEd case", &[' ']), "MIxEd Case");
+        assert_eq!(capitalize_after("a--b", &['-']), "A--B");
+    }
+
+    #[test]
+    fn test_rename_trim_too_long_reports_structured_code() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("abc.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Trim {
+            count: 5,
+            position: Position::Start,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::TrimTooLong);
+        assert_eq!(res.trim_overflow, Some(TrimOverflow { count: 5, len: 3 }));
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_rename_reports_changed_portion() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("old_name_v1.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Replace {
+            from: "v1".into(),
+            to: "v10".into(),
+            use_regex: false,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(
+            res.diff.unwrap(),
+            NameDiff {
+                prefix: "old_name_v1".into(),
+                removed: "".into(),
+                added: "0".into(),
+                suffix: ".txt".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_name_diff_does_not_overlap() {
+        let diff = NameDiff::between("aaa", "aaaa");
+        assert_eq!(diff.prefix, "aaa");
+        assert_eq!(diff.added, "a");
+        assert_eq!(diff.suffix, "");
+
+        let diff = NameDiff::between("写真.jpg", "画像.jpg");
+        assert_eq!(diff.prefix, "");
+        assert_eq!(diff.removed, "写真");
+        assert_eq!(diff.added, "画像");
+        assert_eq!(diff.suffix, ".jpg");
+    }
+
+    #[test]
+    fn test_rename_compatibility_nfkc() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("①ﬁｌｅ.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Compatibility {
+            form: CompatibilityForm::Nfkc,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "1file.txt");
+    }
+
+    #[test]
+    fn test_rename_compatibility_composes_hangul() {
+        let dir = tempdir().unwrap();
+        // 한글 as conjoining jamo, as some archivers write it
+        let file_path = dir
+            .path()
+            .join("\u{1112}\u{1161}\u{11AB}\u{1100}\u{1173}\u{11AF}.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Compatibility {
+            form: CompatibilityForm::Nfkc,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "\u{D55C}\u{AE00}.txt");
+        assert!(dir.path().join("한글.txt").exists());
+    }
+
+    #[test]
+    fn test_nfkc_covers_more_than_hankaku() {
+        let input = "①ﬁＡＢＣ";
+        assert_eq!(to_hankaku(input), "①ﬁABC");
+        assert_eq!(input.nfkc().collect::<String>(), "1fiABC");
+    }
+
+    #[test]
+    fn test_rename_group_digits_strips_commas() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("invoice_1,250,000.csv");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::GroupDigits {
+            separator: "".into(),
+            strip: vec![',', '_'],
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "invoice_1250000.csv");
+    }
+
+    #[test]
+    fn test_group_digits() {
+        assert_eq!(group_digits("total 1000000", "_", &[]), "total 1_000_000");
+        assert_eq!(
+            group_digits("1_000_000 and 999", "'", &['_']),
+            "1'000'000 and 999"
+        );
+        // Not thousands grouping: pieces are handled separately
+        assert_eq!(group_digits("2023_01_05", "", &['_']), "2023_01_05");
+        assert_eq!(group_digits("12345_01", ",", &['_']), "12,345_01");
+    }
+
+    #[test]
+    fn test_validate_name_windows_rules() {
+        assert!(check_name("report.txt", true).valid);
+
+        let v = check_name("a<b>?.txt", true);
+        assert!(!v.valid);
+        assert_eq!(v.illegal_chars, vec!['<', '>', '?']);
+
+        assert!(check_name("con.txt", true).reserved);
+        assert!(check_name("LPT1", true).reserved);
+        assert!(!check_name("console.txt", true).reserved);
+        assert!(check_name("name. ", true).trailing_dot_or_space);
+        assert!(check_name(&"a".repeat(256), true).too_long);
+        assert!(check_name("", true).empty);
+    }
+
+    #[test]
+    fn test_validate_name_unix_rules() {
+        assert!(check_name("con.txt", false).valid);
+        assert!(check_name("a:b?.txt ", false).valid);
+        assert_eq!(check_name("a/b", false).illegal_chars, vec!['/']);
+        assert!(check_name("..", false).reserved);
+        // 255 bytes is the limit, multi-byte chars count by byte
+        assert!(!check_name(&"a".repeat(255), false).too_long);
+        assert!(check_name(&"あ".repeat(86), false).too_long);
+    }
+
+    #[test]
+    fn test_rename_rejects_illegal_name() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("a.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Fixed {
+            name: "sub/dir".into(),
+            keep_ext: true,
+            expand_tokens: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::InvalidName);
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_rename_move_number_to_start() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("Document_001.pdf");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::MoveNumber {
+            to: Position::Start,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "001_Document.pdf");
+    }
+
+    #[test]
+    fn test_rename_move_number_already_at_target() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("001_Document.pdf");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::MoveNumber {
+            to: Position::Start,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::Unchanged);
+    }
+
+    #[test]
+    fn test_move_number() {
+        assert_eq!(
+            move_number("Scan-12-final", &Position::End).unwrap(),
+            "Scan-final-12"
+        );
+        assert_eq!(move_number("Doc7", &Position::Start).unwrap(), "7_Doc");
+        assert_eq!(move_number("a 3", &Position::End).unwrap(), "a 3");
+        assert!(move_number("nodigits", &Position::Start).is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rename_non_utf8_name_by_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join(OsStr::from_bytes(b"caf\xe9.txt"));
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Add {
+            text: "new_".into(),
+            position: Position::Start,
+            repad_trailing: None,
+        };
+        let bytes = file_path.as_os_str().as_bytes().to_vec();
+        let res = handle_rename_bytes(bytes.clone(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::Success);
+        assert_eq!(res.path_bytes.unwrap(), bytes);
+        assert_eq!(res.new_name_bytes.unwrap(), b"new_caf\xe9.txt");
+        assert!(dir
+            .path()
+            .join(OsStr::from_bytes(b"new_caf\xe9.txt"))
+            .exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rename_non_utf8_name_text_command_unsupported() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join(OsStr::from_bytes(b"caf\xe9.txt"));
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+        let res = handle_rename_bytes(file_path.as_os_str().as_bytes().to_vec(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::InvalidFilename);
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_restore_from_journal() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("journal.jsonl");
+        let options = RenameOptions {
+            journal_path: Some(journal.to_str().unwrap().into()),
+            ..Default::default()
+        };
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+
+        for (file_path, name) in [(&a, "c"), (&b, "d")] {
+            let cmd = RenameCommand::Fixed {
+                name: name.into(),
+                keep_ext: true,
+                expand_tokens: false,
+            };
+            let res = handle_rename(
+                file_path.to_str().unwrap().into(),
+                cmd,
+                Some(options.clone()),
+            );
+            assert_eq!(res.code, ResultCode::Success);
+        }
+
+        // Occupy one original name so that entry can't be reversed
+        File::create(&a).unwrap();
+        let results = restore_from_journal(journal.to_str().unwrap().into());
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].code, ResultCode::Success);
+        assert_eq!(results[0].new_name.as_deref(), Some("b.txt"));
+        assert_eq!(results[1].code, ResultCode::TargetExists);
+        assert!(b.exists());
+        assert!(dir.path().join("c.txt").exists());
+    }
+
+    /// Minimal JPEG whose EXIF block holds only `DateTimeOriginal`.
+    fn jpeg_with_exif_date(date: &[u8; 19]) -> Vec<u8> {
+        let mut tiff: Vec<u8> = b"II\x2a\x00\x08\x00\x00\x00".to_vec();
+        // IFD0: one entry pointing at the Exif IFD (offset 26)
+        tiff.extend_from_slice(&[1, 0, 0x69, 0x87, 4, 0, 1, 0, 0, 0, 26, 0, 0, 0, 0, 0, 0, 0]);
+        // Exif IFD: DateTimeOriginal, ASCII, 20 bytes at offset 44
+        tiff.extend_from_slice(&[1, 0, 0x03, 0x90, 2, 0, 20, 0, 0, 0, 44, 0, 0, 0, 0, 0, 0, 0]);
+        tiff.extend_from_slice(date);
+        tiff.push(0);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn test_rename_exif_date() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("DSC0001.jpg");
+        fs::write(&file_path, jpeg_with_exif_date(b"2023:05:01 12:34:56")).unwrap();
+
+        let cmd = RenameCommand::ExifDate {
+            format: "%Y-%m-%d_%H%M%S".into(),
+            keep_ext: true,
+            fallback_to_mtime: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "2023-05-01_123456.jpg");
+    }
+
+    #[test]
+    fn test_rename_exif_date_missing() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("notes.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::ExifDate {
+            format: "%Y".into(),
+            keep_ext: true,
+            fallback_to_mtime: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::NoExifDate);
+        assert_eq!(res.status, "No EXIF date");
+    }
+
+    #[test]
+    fn test_rename_exif_date_falls_back_to_mtime() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("notes.txt");
+        let file = File::create(&file_path).unwrap();
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        file.set_modified(mtime).unwrap();
+
+        let cmd = RenameCommand::ExifDate {
+            format: "%Y%m%d".into(),
+            keep_ext: true,
+            fallback_to_mtime: true,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        let expected = DateTime::<Local>::from(mtime).format("%Y%m%d.txt");
+        assert_eq!(res.new_name.unwrap(), expected.to_string());
+    }
+
+    #[test]
+    fn test_format_datetime_rejects_bad_pattern() {
+        let dt = NaiveDate::from_ymd_opt(2024, 1, 2)
+            .unwrap()
+            .and_hms_opt(3, 4, 5)
+            .unwrap();
+        assert_eq!(format_datetime(&dt, "%Y-%m-%d").unwrap(), "2024-01-02");
+        assert!(format_datetime(&dt, "%Q").is_err());
+    }
+
+    #[test]
+    fn test_rename_collapse_repeats() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("file___name---v2.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::CollapseRepeats {
+            chars: vec!['_', '-'],
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "file_name-v2.txt");
+    }
+
+    #[test]
+    fn test_collapse_repeats_keeps_mixed_runs() {
+        assert_eq!(collapse_repeats("a_-_b", &['_', '-']), "a_-_b");
+        assert_eq!(collapse_repeats("a__--__b", &['_', '-']), "a_-_b");
+        assert_eq!(collapse_repeats("aa  bb", &[' ']), "aa bb");
+    }
+
+    #[test]
+    fn test_rename_batch_numbers_serial_by_position() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["b.jpg", "a.jpg"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().into()
+            })
+            .collect();
+
+        let cmd = RenameCommand::Serial {
+            prefix: "img_".into(),
+            suffix: "".into(),
+            number: 9,
+            pad: 2,
+            keep_ext: true,
+            keep_original: false,
+            pad_side: PadSide::Left,
+            pad_char: '0',
+            sign: SignMode::Negative,
+            radix: Radix::Decimal,
+        };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("img_09.jpg"));
+        assert_eq!(results[1].new_name.as_deref(), Some("img_10.jpg"));
+    }
+
+    #[test]
+    fn test_rename_batch_name_filter() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["IMG_001.jpg", "notes.txt"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().into()
+            })
+            .collect();
+
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Lower,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+        let options = RenameOptions {
+            name_filter: Some(r"^IMG_\d+".into()),
+            ..Default::default()
+        };
+        let results = handle_rename_batch(paths, cmd, Some(options));
+
+        assert_eq!(results[0].new_name.as_deref(), Some("img_001.jpg"));
+        assert_eq!(results[1].code, ResultCode::FilteredOut);
+        assert_eq!(results[1].status, "Filtered out");
+        assert!(dir.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_rename_batch_invalid_name_filter() {
+        let options = RenameOptions {
+            name_filter: Some("(".into()),
+            ..Default::default()
+        };
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Lower,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+        let results = handle_rename_batch(vec!["a".into(), "b".into()], cmd, Some(options));
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.code == ResultCode::InvalidInput));
+    }
+
+    #[test]
+    fn test_rename_recursive() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        File::create(dir.path().join("IMG_1.JPG")).unwrap();
+        File::create(dir.path().join("sub").join("IMG_2.JPG")).unwrap();
+        File::create(dir.path().join("sub").join("keep.TXT")).unwrap();
+
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Lower,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+        let options = RenameOptions {
+            name_filter: Some("^IMG_".into()),
+            ..Default::default()
+        };
+        let results = handle_rename_recursive(
+            dir.path().to_str().unwrap().into(),
+            cmd,
+            Some(options),
+            None,
+        );
+
+        assert_eq!(results.len(), 3);
+        assert!(dir.path().join("img_1.JPG").exists());
+        assert!(dir.path().join("sub").join("img_2.JPG").exists());
+        assert!(dir.path().join("sub").join("keep.TXT").exists());
+        assert!(dir.path().join("sub").is_dir());
+    }
+
+    #[test]
+    fn test_rename_include_size() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("data.bin");
+        fs::write(&file_path, [0u8; 42]).unwrap();
+
+        let cmd = RenameCommand::Add {
+            text: "_v2".into(),
+            position: Position::End,
+            repad_trailing: None,
+        };
+        let options = RenameOptions {
+            include_size: true,
+            ..Default::default()
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, Some(options));
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.size_bytes, Some(42));
+    }
+
+    #[test]
+    fn test_rename_size_not_reported_by_default() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("data.bin");
+        fs::write(&file_path, [0u8; 42]).unwrap();
+
+        let cmd = RenameCommand::Add {
+            text: "_v2".into(),
+            position: Position::End,
+            repad_trailing: None,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.size_bytes, None);
+    }
+
+    #[test]
+    fn test_rename_safe_mode_blocks_extension_removal() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("report.pdf");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Fixed {
+            name: "report".into(),
+            keep_ext: false,
+            expand_tokens: false,
+        };
+        let options = RenameOptions {
+            safe_mode: true,
+            ..Default::default()
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, Some(options));
+
+        assert_eq!(res.code, ResultCode::BlockedBySafeMode);
+        assert_eq!(res.status, "Blocked by safe mode: extension removed");
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_safe_mode_violation() {
+        assert_eq!(safe_mode_violation("report.pdf", "report_v2.pdf"), None);
+        assert_eq!(
+            safe_mode_violation("report.pdf", "r.pdf").unwrap(),
+            "name reduced to 1 character(s)"
+        );
+        assert_eq!(
+            safe_mode_violation("IMG_0001.jpg", "vacation.png").unwrap(),
+            "92% of the name changed"
+        );
+        // Already-short names may stay short
+        assert_eq!(safe_mode_violation("a.txt", "b.txt"), None);
+    }
+
+    #[test]
+    fn test_rename_mixed_commands() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+
+        let ops = vec![
+            (
+                a.to_str().unwrap().to_string(),
+                RenameCommand::Case {
+                    mode: CaseMode::Upper,
+                    split: ExtSplit::LastDot,
+                    only_if: CaseCondition::Always,
+                    include_ext: false,
+                },
+            ),
+            (
+                b.to_str().unwrap().to_string(),
+                RenameCommand::Add {
+                    text: "_old".into(),
+                    position: Position::End,
+                    repad_trailing: None,
+                },
+            ),
+        ];
+        let results = handle_rename_mixed(ops, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("A.txt"));
+        assert_eq!(results[1].new_name.as_deref(), Some("b_old.txt"));
+    }
+
+    #[test]
+    fn test_rename_mixed_duplicate_target() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.md");
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+
+        let ops = vec![
+            (
+                a.to_str().unwrap().to_string(),
+                RenameCommand::Fixed {
+                    name: "c".into(),
+                    keep_ext: false,
+                    expand_tokens: false,
+                },
+            ),
+            (
+                b.to_str().unwrap().to_string(),
+                RenameCommand::Replace {
+                    from: "b.md".into(),
+                    to: "c".into(),
+                    use_regex: false,
+                    ignore_case: false,
+                    multi_line: false,
+                    dot_matches_new_line: false,
+                    preserve_ext: false,
+                    literal_replacement: false,
+                    preserve_case: false,
+                },
+            ),
+        ];
+        let results = handle_rename_mixed(ops, None);
+
+        assert_eq!(results[0].status, "Success");
+        assert_eq!(results[1].code, ResultCode::DuplicateTarget);
+        assert_eq!(results[1].status, "Duplicate target in batch: c");
+        assert!(b.exists());
+    }
+
+    #[test]
+    fn test_rename_batch_duplicate_target() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["one.txt", "two.txt"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let cmd = RenameCommand::Fixed {
+            name: "same".into(),
+            keep_ext: true,
+            expand_tokens: false,
+        };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("same.txt"));
+        assert_eq!(results[1].code, ResultCode::DuplicateTarget);
+        assert!(dir.path().join("two.txt").exists());
+    }
+
+    #[test]
+    fn test_rename_replace_regex_flags() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("IMG_Draft_img.png");
+        File::create(&file_path).unwrap();
+
+        // Builder flag makes the match case-insensitive; the inline `(?-i:...)`
+        // group opts back out for its part of the pattern.
+        let cmd = RenameCommand::Replace {
+            from: "img_(?-i:Draft)".into(),
+            to: "photo_".into(),
+            use_regex: true,
+            ignore_case: true,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "photo__img.png");
+    }
+
+    #[test]
+    fn test_replace_flags_default_when_omitted() {
+        let cmd: RenameCommand = serde_json::from_str(
+            r#"{"mode":"Replace","config":{"from":"a","to":"b","use_regex":true}}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            cmd,
+            RenameCommand::Replace {
+                ignore_case: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_rename_add_checksum() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("report.pdf");
+        fs::write(&file_path, b"123456789").unwrap();
+
+        let cmd = RenameCommand::AddChecksum {
+            algo: ChecksumAlgo::Crc32,
+            length: 8,
+            max_bytes: None,
+            timeout_ms: None,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "report_cbf43926.pdf");
+    }
+
+    #[test]
+    fn test_rename_add_checksum_sha1_truncated() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("notes.txt");
+        fs::write(&file_path, b"abc").unwrap();
+
+        let cmd = RenameCommand::AddChecksum {
+            algo: ChecksumAlgo::Sha1,
+            length: 6,
+            max_bytes: None,
+            timeout_ms: None,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "notes_a9993e.txt");
+    }
+
+    #[test]
+    fn test_rename_trim_both() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("xx_report_old.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::TrimBoth { start: 3, end: 4 };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "report.txt");
+    }
+
+    #[test]
+    fn test_rename_trim_both_too_long() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("abcd.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::TrimBoth { start: 2, end: 2 };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::TrimTooLong);
+        assert_eq!(res.trim_overflow, Some(TrimOverflow { count: 4, len: 4 }));
+        assert!(file_path.exists());
+
+        // Same wording as a one-sided trim of the same total
+        let cmd = RenameCommand::Trim {
+            count: 4,
+            position: Position::End,
+        };
+        let trim = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+        assert_eq!(res.status, trim.status);
+    }
+
+    #[test]
+    fn test_rename_trim_both_dotfile() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join(".xxbashrc_old");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::TrimBoth { start: 2, end: 4 };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.new_name.as_deref(), Some(".bashrc"));
+    }
+
+    #[test]
+    fn test_rename_strip_control_chars() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("draft\tcopy.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::StripControlChars {
+            replacement: "_".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "draft_copy.txt");
+    }
+
+    #[test]
+    fn test_preview_many_does_not_rename() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["IMG_1.jpg", "IMG_2.jpg"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let missing = dir.path().join("gone.jpg").to_str().unwrap().to_string();
+
+        let cmd = RenameCommand::Replace {
+            from: r"^img_(\d)".into(),
+            to: "photo-$1".into(),
+            use_regex: true,
+            ignore_case: true,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+        let mut all = paths.clone();
+        all.push(missing.clone());
+        let preview = preview_many(all, cmd);
+
+        assert_eq!(
+            preview,
+            vec![
+                (paths[0].clone(), "photo-1.jpg".to_string()),
+                (paths[1].clone(), "photo-2.jpg".to_string()),
+                (missing, String::new()),
+            ]
+        );
+        assert!(dir.path().join("IMG_1.jpg").exists());
+    }
+
+    fn rename_dotfile(cmd: RenameCommand) -> RenameResult {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join(".gitignore");
+        File::create(&file_path).unwrap();
+        handle_rename(file_path.to_str().unwrap().into(), cmd, None)
+    }
+
+    #[test]
+    fn test_dotfile_add_keeps_leading_dot() {
+        let res = rename_dotfile(RenameCommand::Add {
+            text: "old_".into(),
+            position: Position::Start,
+            repad_trailing: None,
+        });
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), ".old_gitignore");
+    }
+
+    #[test]
+    fn test_dotfile_case_keeps_leading_dot() {
+        let res = rename_dotfile(RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        });
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), ".GITIGNORE");
+    }
+
+    #[test]
+    fn test_dotfile_convert_keeps_ascii_dot() {
+        let res = rename_dotfile(RenameCommand::Convert {
+            mode: WidthMode::Zenkaku,
+            split: ExtSplit::LastDot,
+        });
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), ".ｇｉｔｉｇｎｏｒｅ");
+    }
+
+    #[test]
+    fn test_dotfile_trim_keeps_leading_dot() {
+        let res = rename_dotfile(RenameCommand::Trim {
+            count: 3,
+            position: Position::Start,
+        });
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), ".ignore");
+    }
+
+    #[test]
+    fn test_rename_replace_no_match() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("photo.jpg");
+        File::create(&file_path).unwrap();
+
+        for use_regex in [false, true] {
+            let cmd = RenameCommand::Replace {
+                from: "IMG".into(),
+                to: "img".into(),
+                use_regex,
+                ignore_case: false,
+                multi_line: false,
+                dot_matches_new_line: false,
+                preserve_ext: false,
+                literal_replacement: false,
+                preserve_case: false,
+            };
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+            assert_eq!(res.code, ResultCode::NoMatch);
+            assert_eq!(res.status, "No match");
+            assert!(res.new_name.is_none());
+        }
+    }
+
+    #[test]
+    fn test_pad_serial_sides() {
+        let (sign, dec) = (SignMode::Negative, Radix::Decimal);
+        assert_eq!(pad_serial(5, 4, &PadSide::Left, '0', sign, dec), "0005");
+        assert_eq!(pad_serial(5, 4, &PadSide::Right, '0', sign, dec), "5000");
+        assert_eq!(pad_serial(5, 3, &PadSide::Right, ' ', sign, dec), "5  ");
+        assert_eq!(
+            pad_serial(12345, 3, &PadSide::Left, '0', sign, dec),
+            "12345"
+        );
+    }
+
+    #[test]
+    fn test_pad_serial_radix() {
+        let pad = |n, radix| pad_serial(n, 2, &PadSide::Left, '0', SignMode::Negative, radix);
+        assert_eq!(pad(9, Radix::HexUpper), "09");
+        assert_eq!(pad(10, Radix::HexUpper), "0A");
+        assert_eq!(pad(15, Radix::HexUpper), "0F");
+        assert_eq!(pad(16, Radix::HexUpper), "10");
+        assert_eq!(pad(255, Radix::HexLower), "ff");
+        assert_eq!(pad(8, Radix::Octal), "10");
+        assert_eq!(pad(-10, Radix::HexUpper), "-0A");
+    }
+
+    #[test]
+    fn test_rename_serial_hex() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["a.bin", "b.bin"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                File::create(&path).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let cmd: RenameCommand = serde_json::from_str(
+            r#"{"mode":"Serial","config":{"prefix":"frame_","suffix":"","number":9,
+                "pad":2,"keep_ext":true,"keep_original":false,"radix":"hexupper"}}"#,
+        )
+        .unwrap();
+
+        let results = handle_rename_batch(paths, cmd, None);
+        let names: Vec<_> = results.iter().map(|r| r.new_name.as_deref()).collect();
+        assert_eq!(names, [Some("frame_09.bin"), Some("frame_0A.bin")]);
+    }
+
+    #[test]
+    fn test_pad_serial_signs() {
+        let left = &PadSide::Left;
+        for (sign, expected) in [
+            (SignMode::Negative, ["-03", "00", "05"]),
+            (SignMode::Always, ["-03", "00", "+05"]),
+            (SignMode::AlwaysWithZero, ["-03", "+00", "+05"]),
+        ] {
+            let padded: Vec<String> = [-3, 0, 5]
+                .into_iter()
+                .map(|n| pad_serial(n, 2, left, '0', sign, Radix::Decimal))
+                .collect();
+            assert_eq!(padded, expected, "{:?}", sign);
+        }
+    }
+
+    #[test]
+    fn test_rename_serial_negative_start() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["a.dat", "b.dat", "c.dat"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                File::create(&path).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let cmd: RenameCommand = serde_json::from_str(
+            r#"{"mode":"Serial","config":{"prefix":"off","suffix":"","number":-1,
+                "pad":2,"keep_ext":true,"keep_original":false,"sign":"always"}}"#,
+        )
+        .unwrap();
+
+        let results = handle_rename_batch(paths, cmd, None);
+        let names: Vec<_> = results.iter().map(|r| r.new_name.as_deref()).collect();
+        assert_eq!(
+            names,
+            [Some("off-01.dat"), Some("off00.dat"), Some("off+01.dat")]
+        );
+    }
+
+    #[test]
+    fn test_rename_serial_right_pad() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("log.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Serial {
+            prefix: "rec".into(),
+            suffix: "".into(),
+            number: 5,
+            pad: 4,
+            keep_ext: true,
+            keep_original: false,
+            pad_side: PadSide::Right,
+            pad_char: '0',
+            sign: SignMode::Negative,
+            radix: Radix::Decimal,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "rec5000.txt");
+    }
+
+    #[test]
+    fn test_serial_pad_defaults_when_omitted() {
+        let cmd: RenameCommand = serde_json::from_str(
+            r#"{"mode":"Serial","config":{"prefix":"","suffix":"","number":7,"pad":3,"keep_ext":false,"keep_original":false}}"#,
+        )
+        .unwrap();
+
+        let RenameCommand::Serial {
+            pad_side, pad_char, ..
+        } = cmd
+        else {
+            panic!("expected Serial");
+        };
+        assert!(matches!(pad_side, PadSide::Left));
+        assert_eq!(pad_char, '0');
+    }
+
+    #[test]
+    fn test_rename_folder_index_ignores_selection_order() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("a_subdir")).unwrap();
+        for name in ["b.txt", "a.txt", "c.txt"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+
+        let cmd = RenameCommand::FolderIndex {
+            pad: 2,
+            position: Position::Start,
+        };
+        let paths = ["c.txt", "a.txt"]
+            .iter()
+            .map(|name| dir.path().join(name).to_str().unwrap().to_string())
+            .collect();
+        let results = handle_rename_batch(paths, cmd, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("03_c.txt"));
+        assert_eq!(results[1].new_name.as_deref(), Some("01_a.txt"));
+    }
+
+    #[test]
+    fn test_case_probe_matches_filesystem() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("probe.txt")).unwrap();
+        let expected = dir.path().join("PROBE.TXT").exists();
+
+        assert_eq!(is_case_insensitive(dir.path()), expected);
+        // Nothing is written, not even for a folder that doesn't exist yet
+        assert_eq!(is_case_insensitive(&dir.path().join("sub")), expected);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_rename_batch_case_only_duplicate_targets() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+        let insensitive = is_case_insensitive(dir.path());
+
+        let ops = vec![
+            (
+                a.to_str().unwrap().to_string(),
+                RenameCommand::Fixed {
+                    name: "Report.txt".into(),
+                    keep_ext: false,
+                    expand_tokens: false,
+                },
+            ),
+            (
+                b.to_str().unwrap().to_string(),
+                RenameCommand::Fixed {
+                    name: "report.txt".into(),
+                    keep_ext: false,
+                    expand_tokens: false,
+                },
+            ),
+        ];
+        let results = handle_rename_mixed(ops, None);
+
+        assert_eq!(results[0].status, "Success");
+        if insensitive {
+            assert_eq!(results[1].code, ResultCode::DuplicateTarget);
+        } else {
+            assert_eq!(results[1].status, "Success");
+        }
+    }
+
+    #[test]
+    fn test_apply_name_list() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["scan_2.png", "scan_1.png", "scan_3.png"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let names_file = dir.path().join("names.txt");
+        fs::write(&names_file, "cover\r\npreface\n").unwrap();
+
+        let results = apply_name_list(paths, names_file.to_str().unwrap().into(), true, None);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].new_name.as_deref(), Some("cover.png"));
+        assert_eq!(results[1].new_name.as_deref(), Some("preface.png"));
+        assert_eq!(results[2].code, ResultCode::NameListMismatch);
+        assert_eq!(results[2].status, "Name list has 2 names for 3 files");
+        assert!(dir.path().join("scan_3.png").exists());
+    }
+
+    #[test]
+    fn test_apply_name_list_surplus_names() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("page.tif");
+        File::create(&file_path).unwrap();
+        let names_file = dir.path().join("names.txt");
+        fs::write(&names_file, "first.tif\nsecond.tif\n").unwrap();
+
+        let results = apply_name_list(
+            vec![file_path.to_str().unwrap().into()],
+            names_file.to_str().unwrap().into(),
+            false,
+            None,
+        );
+
+        assert_eq!(results[0].new_name.as_deref(), Some("first.tif"));
+        assert_eq!(results[1].path, names_file.to_str().unwrap());
+        assert_eq!(results[1].code, ResultCode::NameListMismatch);
+    }
+
+    #[test]
+    fn test_apply_name_list_trailing_blank_lines() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["1.pdf", "2.pdf"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let names_file = dir.path().join("names.txt");
+        fs::write(&names_file, "intro.pdf\noutro.pdf\n\n  \n").unwrap();
+
+        let results = apply_name_list(paths, names_file.to_str().unwrap().into(), false, None);
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.code == ResultCode::Success));
+    }
+
+    #[test]
+    fn test_apply_name_list_braces_verbatim() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["1.pdf", "2.pdf"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let names_file = dir.path().join("names.txt");
+        fs::write(
+            &names_file,
+            "a{b}.pdf
+{date}.pdf
+",
+        )
+        .unwrap();
+
+        let results = apply_name_list(paths, names_file.to_str().unwrap().into(), false, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("a{b}.pdf"));
+        assert_eq!(results[1].new_name.as_deref(), Some("{date}.pdf"));
+        assert!(dir.path().join("a{b}.pdf").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rename_batch_reports_hardlinks() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        File::create(&a).unwrap();
+        fs::hard_link(&a, &b).unwrap();
+
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+        let options = RenameOptions {
+            include_inode: true,
+            ..Default::default()
+        };
+        let paths = vec![a.to_str().unwrap().into(), b.to_str().unwrap().into()];
+        let results = handle_rename_batch(paths, cmd, Some(options));
+
+        assert!(results[0].inode.is_some());
+        assert_eq!(
+            (results[0].inode, results[0].device),
+            (results[1].inode, results[1].device)
+        );
+    }
+
+    #[test]
+    fn test_apply_dictionary_whole_words_only() {
+        let dict = parse_dictionary("Dr,Doctor\nSt,Street\n", AbbrevDirection::Expand).unwrap();
+
+        assert_eq!(
+            apply_dictionary("Dr_Smith_Drive_St", &dict),
+            "Doctor_Smith_Drive_Street"
+        );
+        assert_eq!(apply_dictionary("Drst", &dict), "Drst");
+    }
+
+    #[test]
+    fn test_rename_abbreviations_contract() {
+        let dir = tempdir().unwrap();
+        let dict_path = dir.path().join("abbr.csv");
+        fs::write(&dict_path, "Dr, Doctor\nSt,Street\n").unwrap();
+        let file_path = dir.path().join("Doctor Who Street.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Abbreviations {
+            dictionary: dict_path.to_str().unwrap().into(),
+            direction: AbbrevDirection::Contract,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "Dr Who St.txt");
+    }
+
+    #[test]
+    fn test_rename_abbreviations_missing_dictionary() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("Dr.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Abbreviations {
+            dictionary: dir.path().join("none.csv").to_str().unwrap().into(),
+            direction: AbbrevDirection::Expand,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::IoError);
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_shift_number() {
+        assert_eq!(shift_number("ep05", 10, 2).unwrap().unwrap(), "ep15");
+        assert_eq!(shift_number("ep05_v2", -3, 3).unwrap().unwrap(), "ep002_v2");
+        assert_eq!(shift_number("ep05", -10, 2).unwrap().unwrap(), "ep00");
+        assert!(shift_number("intro", 1, 2).is_none());
+    }
+
+    #[test]
+    fn test_rename_shift_number() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("ep05.mkv");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::ShiftNumber { delta: 10, pad: 2 };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "ep15.mkv");
+    }
+
+    #[test]
+    fn test_undo_last_batch_only_once() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("journal.jsonl");
+        let options = RenameOptions {
+            journal_path: Some(journal.to_str().unwrap().into()),
+            ..Default::default()
+        };
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+
+        let first = handle_rename(
+            a.to_str().unwrap().into(),
+            RenameCommand::Fixed {
+                name: "first".into(),
+                keep_ext: true,
+                expand_tokens: false,
+            },
+            Some(options.clone()),
+        );
+        assert_eq!(first.code, ResultCode::Success);
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+        let paths = vec![
+            dir.path().join("first.txt").to_str().unwrap().into(),
+            b.to_str().unwrap().into(),
+        ];
+        handle_rename_batch(paths, cmd, Some(options));
+
+        let undo = undo_last_batch(journal.to_str().unwrap().into());
+        assert_eq!(undo.code, ResultCode::Success);
+        assert!(undo.batch.is_some());
+        assert_eq!(undo.results.len(), 2);
+        assert!(dir.path().join("first.txt").exists());
+        assert!(b.exists());
+
+        // The earlier single rename is now the last batch
+        let undo_again = undo_last_batch(journal.to_str().unwrap().into());
+        assert_eq!(undo_again.results.len(), 1);
+        assert_ne!(undo_again.batch, undo.batch);
+        assert!(a.exists());
+
+        let nothing = undo_last_batch(journal.to_str().unwrap().into());
+        assert_eq!(nothing.code, ResultCode::NothingToUndo);
+        assert_eq!(nothing.status, "Nothing to undo");
+    }
+
+    #[test]
+    fn test_rename_fixed_braces_without_tokens() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("draft.txt");
+        File::create(&file_path).unwrap();
+
+        // The `{name, keep_ext}` shape the frontend sends
+        let cmd: RenameCommand =
+            serde_json::from_str(r#"{"mode":"Fixed","config":{"name":"set {a}","keep_ext":true}}"#)
+                .unwrap();
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.new_name.as_deref(), Some("set {a}.txt"));
+    }
+
+    #[test]
+    fn test_expand_tokens() {
+        let today = Local::now().format("%Y").to_string();
+
+        assert_eq!(
+            expand_tokens("report_{date:%Y}").unwrap(),
+            format!("report_{}", today)
+        );
+        assert_eq!(expand_tokens("{{literal}}").unwrap(), "{literal}");
+        assert_eq!(
+            expand_tokens("a_{nope}").unwrap_err().message,
+            "Unknown token: {nope}"
+        );
+        assert_eq!(
+            expand_tokens("a_{date").unwrap_err().code,
+            ResultCode::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_rename_fixed_with_date_token() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("scan.pdf");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Fixed {
+            name: "report_{date:%Y%m}".into(),
+            keep_ext: true,
+            expand_tokens: true,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(
+            res.new_name.unwrap(),
+            format!("report_{}.pdf", Local::now().format("%Y%m"))
+        );
+    }
+
+    #[test]
+    fn test_analyze_batch_reports_reasons_without_renaming() {
+        let dir = tempdir().unwrap();
+        for name in ["IMG_1.jpg", "IMG_2.jpg", "notes.txt", "same.jpg"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let paths: Vec<String> = ["IMG_1.jpg", "IMG_2.jpg", "notes.txt", "gone.jpg"]
+            .iter()
+            .map(|name| dir.path().join(name).to_str().unwrap().to_string())
+            .collect();
+
+        let cmd = RenameCommand::Fixed {
+            name: "same".into(),
+            keep_ext: true,
+            expand_tokens: false,
+        };
+        let options = RenameOptions {
+            name_filter: Some(r"\.jpg$".into()),
+            ..Default::default()
+        };
+        let report = analyze_batch(paths, cmd, Some(options));
+        let codes: Vec<ResultCode> = report.iter().map(|r| r.code).collect();
+
+        assert_eq!(
+            codes,
+            vec![
+                ResultCode::TargetExists,
+                ResultCode::DuplicateTarget,
+                ResultCode::FilteredOut,
+                ResultCode::NotFound,
+            ]
+        );
+        assert!(dir.path().join("IMG_1.jpg").exists());
+    }
+
+    fn init_counter_store() {
+        COUNTER_DIR.get_or_init(|| {
+            std::env::temp_dir().join(format!("ddrenamer-counters-{}", std::process::id()))
+        });
+    }
+
+    #[test]
+    fn test_counter_continues_across_batches() {
+        init_counter_store();
+        reset_counter("invoice".into(), 41).unwrap();
+        let dir = tempdir().unwrap();
+        let paths = |names: &[&str]| -> Vec<String> {
+            names
+                .iter()
+                .map(|name| {
+                    let p = dir.path().join(name);
+                    File::create(&p).unwrap();
+                    p.to_str().unwrap().to_string()
+                })
+                .collect()
+        };
+        let cmd = || RenameCommand::Counter {
+            key: "invoice".into(),
+            prefix: "INV-".into(),
+            suffix: "".into(),
+            pad: 4,
+            keep_ext: true,
+        };
+
+        // Previewing must not consume numbers
+        preview_many(paths(&["a.pdf"]), cmd());
+        let first = handle_rename_batch(paths(&["a.pdf", "b.pdf"]), cmd(), None);
+        let second = handle_rename_batch(paths(&["c.pdf"]), cmd(), None);
+
+        assert_eq!(first[0].new_name.as_deref(), Some("INV-0041.pdf"));
+        assert_eq!(first[1].new_name.as_deref(), Some("INV-0042.pdf"));
+        assert_eq!(second[0].new_name.as_deref(), Some("INV-0043.pdf"));
+        assert_eq!(peek_counter("invoice").unwrap(), 44);
+    }
+
+    #[test]
+    fn test_counter_reservations_disjoint() {
+        init_counter_store();
+        reset_counter("ticket".into(), 1).unwrap();
+
+        // Two batches prepared before either renames get separate ranges
+        let first = reserve_counter("ticket", 3).unwrap();
+        let second = reserve_counter("ticket", 2).unwrap();
+        assert_eq!((first, second), (1, 4));
+
+        // The earlier batch can't hand back numbers behind a later one
+        release_counter("ticket", 4, 2).unwrap();
+        assert_eq!(peek_counter("ticket").unwrap(), 6);
+        release_counter("ticket", 6, 5).unwrap();
+        assert_eq!(peek_counter("ticket").unwrap(), 5);
+    }
+
+    #[test]
+    fn test_rename_mixed_counter_consecutive() {
+        init_counter_store();
+        reset_counter("receipt".into(), 7).unwrap();
+        let dir = tempdir().unwrap();
+        let counter = || RenameCommand::Counter {
+            key: "receipt".into(),
+            prefix: "R".into(),
+            suffix: "".into(),
+            pad: 2,
+            keep_ext: true,
+        };
+        let mut ops = Vec::new();
+        for folder in ["x", "y"] {
+            fs::create_dir(dir.path().join(folder)).unwrap();
+            let p = dir.path().join(folder).join("scan.png");
+            File::create(&p).unwrap();
+            ops.push((p.to_str().unwrap().to_string(), counter()));
+        }
+
+        let results = handle_rename_mixed(ops, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("R07.png"));
+        assert_eq!(results[1].new_name.as_deref(), Some("R08.png"));
+        assert_eq!(peek_counter("receipt").unwrap(), 9);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_lock_error() {
+        // EBUSY and ETXTBSY share these numbers on Linux and macOS
+        assert!(is_lock_error(&io::Error::from_raw_os_error(16)));
+        assert!(is_lock_error(&io::Error::from_raw_os_error(26)));
+        assert!(!is_lock_error(&io::Error::from_raw_os_error(13)));
+    }
+
+    #[test]
+    fn test_rename_trim_whitespace_full_width() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("\u{3000} report\u{3000}.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::TrimWhitespace {
+            start: true,
+            end: true,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "report.txt");
+    }
+
+    #[test]
+    fn test_rename_trim_whitespace_tabs_one_edge() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("\tmy notes\t.md");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::TrimWhitespace {
+            start: false,
+            end: true,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "\tmy notes.md");
+    }
+
+    #[test]
+    fn test_rename_timestamp_unique_within_same_second() {
+        let dir = tempdir().unwrap();
+        let shot = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let paths: Vec<String> = ["burst_a.jpg", "burst_b.jpg"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap().set_modified(shot).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let expected = DateTime::<Local>::from(shot)
+            .format("%Y%m%d_%H%M%S")
+            .to_string();
+
+        let cmd = RenameCommand::TimeStamp {
+            format: "%Y%m%d_%H%M%S".into(),
+            use_file_time: true,
+            ensure_unique: true,
+        };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        assert_eq!(
+            results[0].new_name.as_deref(),
+            Some(format!("{}.jpg", expected).as_str())
+        );
+        assert_eq!(
+            results[1].new_name.as_deref(),
+            Some(format!("{}_1.jpg", expected).as_str())
+        );
+    }
+
+    #[test]
+    fn test_rename_timestamp_without_unique_collides() {
+        let dir = tempdir().unwrap();
+        let shot = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let paths: Vec<String> = ["a.jpg", "b.jpg"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap().set_modified(shot).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let cmd = RenameCommand::TimeStamp {
+            format: "%Y%m%d_%H%M%S".into(),
+            use_file_time: true,
+            ensure_unique: false,
+        };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        assert_eq!(results[0].code, ResultCode::Success);
+        assert_eq!(results[1].code, ResultCode::DuplicateTarget);
+    }
+
+    #[test]
+    fn test_rename_reformat_date() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("invoice_12-31-2023_final.pdf");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::ReformatDate {
+            from: "%m-%d-%Y".into(),
+            to: "%Y-%m-%d".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "invoice_2023-12-31_final.pdf");
+    }
+
+    #[test]
+    fn test_rename_reformat_date_no_match() {
+        let dir = tempdir().unwrap();
+        // 13 isn't a month, so the only candidate fails strict parsing
+        let file_path = dir.path().join("scan_13-31-2023.pdf");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::ReformatDate {
+            from: "%m-%d-%Y".into(),
+            to: "%Y-%m-%d".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::NoDateMatched);
+        assert_eq!(res.status, "No date matched");
+    }
+
+    #[test]
+    fn test_undo_selected_ops() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("journal.jsonl");
+        let journal_str: String = journal.to_str().unwrap().into();
+        let options = RenameOptions {
+            journal_path: Some(journal_str.clone()),
+            ..Default::default()
+        };
+        let paths: Vec<String> = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+        let results = handle_rename_batch(paths, cmd, Some(options));
+        let batch_id = results[0].batch_id.clone().unwrap();
+        assert!(results
+            .iter()
+            .all(|r| r.batch_id.as_ref() == Some(&batch_id)));
+        let op_b = results[1].op_id.clone().unwrap();
+        assert_ne!(results[0].op_id.as_ref(), Some(&op_b));
+
+        let undone = undo_ops(journal_str.clone(), vec![op_b.clone()]);
+        assert_eq!(undone.len(), 1);
+        assert_eq!(undone[0].code, ResultCode::Success);
+        assert!(dir.path().join("b.txt").exists());
+        assert!(dir.path().join("A.txt").exists());
+
+        let again = undo_ops(journal_str, vec![op_b]);
+        assert_eq!(again[0].code, ResultCode::NotFound);
+        assert_eq!(again[0].status, "Unknown op id");
+    }
+
+    #[test]
+    fn test_rename_strip_emoji_sequences() {
+        let dir = tempdir().unwrap();
+        let file_path = dir
+            .path()
+            .join("旅行🇯🇵_👍🏽_👨\u{200D}👩\u{200D}👧_2024©.jpg");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::StripEmoji {
+            replacement: "".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "旅行___2024©.jpg");
+    }
+
+    #[test]
+    fn test_rename_strip_emoji_replaces_each_sequence_once() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("party🎉🏳\u{FE0F}\u{200D}🌈end.png");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::StripEmoji {
+            replacement: "-".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "party--end.png");
+    }
+
+    #[test]
+    fn test_slugify_max_length_on_word_boundary() {
+        assert_eq!(slugify("Héllo, Wörld! (v2)", "-", Some(12)), "hello-world");
+        assert_eq!(slugify("Extraordinary", "-", Some(5)), "extra");
+        assert_eq!(slugify("  a--b  ", "_", None), "a_b");
+    }
+
+    #[test]
+    fn test_rename_slugify() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("Héllo, Wörld! (v2).MD");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Slugify {
+            separator: "-".into(),
+            max_length: None,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "hello-world-v2.MD");
+    }
+
+    #[test]
+    fn test_convert_width_round_trip() {
+        let wide = convert_width("File 01!".into(), WidthMode::Zenkaku);
+
+        assert_eq!(wide, "Ｆｉｌｅ\u{3000}０１！");
+        assert_eq!(convert_width(wide, WidthMode::Hankaku), "File 01!");
+    }
+
+    #[test]
+    fn test_rename_directory_treats_whole_name_as_stem() {
+        let dir = tempdir().unwrap();
+        let folder = dir.path().join("My.Folder");
+        fs::create_dir(&folder).unwrap();
+        File::create(folder.join("inside.txt")).unwrap();
+
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+        let res = handle_rename(folder.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "MY.FOLDER");
+        assert!(dir.path().join("MY.FOLDER").join("inside.txt").exists());
+    }
+
+    #[test]
+    fn test_rename_swap_extension_only_matching() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["a.JPEG", "b.jpeg", "c.png"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let cmd = RenameCommand::SwapExtension {
+            from: ".jpeg".into(),
+            to: "jpg".into(),
+        };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("a.jpg"));
+        assert_eq!(results[1].new_name.as_deref(), Some("b.jpg"));
+        assert_eq!(results[2].code, ResultCode::Unchanged);
+        assert_eq!(results[2].status, "Unchanged");
+    }
+
+    #[test]
+    fn test_count_changes() {
+        let dir = tempdir().unwrap();
+        for name in ["a_v1.txt", "b_v1.txt", "c_v2.txt", "a_v2.txt"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let paths: Vec<String> = ["a_v1.txt", "b_v1.txt", "c_v2.txt", "missing_v1.txt"]
+            .iter()
+            .map(|name| dir.path().join(name).to_str().unwrap().to_string())
+            .collect();
+
+        let cmd = RenameCommand::Replace {
+            from: "v1".into(),
+            to: "v2".into(),
+            use_regex: false,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+        let count = count_changes(paths, cmd, None);
+
+        assert_eq!(
+            count,
+            ChangeCount {
+                will_change: 1,
+                no_op: 1,
+                conflict: 1,
+                skipped: 1,
+            }
+        );
+        assert!(dir.path().join("b_v1.txt").exists());
+    }
+
+    #[test]
+    fn test_rename_serial_ext_token() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("data.csv");
+        File::create(&file_path).unwrap();
+
+        // keep_ext is ignored once `{ext}` places the extension explicitly
+        let cmd = RenameCommand::Serial {
+            prefix: "backup_".into(),
+            suffix: ".{ext}.bak".into(),
+            number: 3,
+            pad: 2,
+            keep_ext: true,
+            keep_original: false,
+            pad_side: PadSide::Left,
+            pad_char: '0',
+            sign: SignMode::Negative,
+            radix: Radix::Decimal,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "backup_03.csv.bak");
+    }
+
+    #[test]
+    fn test_rename_regex_sort_serial() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["disc_title10.mkv", "disc_title2.mkv", "disc_title1.mkv"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let cmd = RenameCommand::RegexSortSerial {
+            pattern: r"title(\d+)".into(),
+            key_group: 1,
+            prefix: "ep".into(),
+            pad: 2,
+        };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        // Numeric, not lexical: 1 < 2 < 10
+        assert_eq!(results[0].new_name.as_deref(), Some("ep03.mkv"));
+        assert_eq!(results[1].new_name.as_deref(), Some("ep02.mkv"));
+        assert_eq!(results[2].new_name.as_deref(), Some("ep01.mkv"));
+    }
+
+    #[test]
+    fn test_case_split_policy() {
+        let dir = tempdir().unwrap();
+        for (split, expected) in [
+            (ExtSplit::LastDot, "MY.CONFIG.json"),
+            (ExtSplit::FirstDot, "MY.config.json"),
+        ] {
+            let file_path = dir.path().join("my.config.json");
+            File::create(&file_path).unwrap();
+            let cmd = RenameCommand::Case {
+                mode: CaseMode::Upper,
+                split,
+                only_if: CaseCondition::Always,
+                include_ext: false,
+            };
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(res.code, ResultCode::Success);
+            assert_eq!(res.new_name.as_deref(), Some(expected));
+            fs::remove_file(dir.path().join(expected)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_convert_split_policy() {
+        let dir = tempdir().unwrap();
+        for (split, expected) in [
+            (ExtSplit::LastDot, "ｍｙ．ｃｏｎｆｉｇ.json"),
+            (ExtSplit::FirstDot, "ｍｙ.config.json"),
+        ] {
+            let file_path = dir.path().join("my.config.json");
+            File::create(&file_path).unwrap();
+            let cmd = RenameCommand::Convert {
+                mode: WidthMode::Zenkaku,
+                split,
+            };
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(res.code, ResultCode::Success);
+            assert_eq!(res.new_name.as_deref(), Some(expected));
+            fs::remove_file(dir.path().join(expected)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_apply_names() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("IMG_001.jpg");
+        let b = dir.path().join("IMG_002.jpg");
+        let c = dir.path().join("IMG_003.jpg");
+        for p in [&a, &b, &c] {
+            File::create(p).unwrap();
+        }
+        let pairs = vec![
+            (a.to_str().unwrap().to_string(), "beach.jpg".to_string()),
+            (b.to_str().unwrap().to_string(), "sunset.jpg".to_string()),
+            (c.to_str().unwrap().to_string(), "sunset.jpg".to_string()),
+        ];
+
+        let results = apply_names(pairs, None);
+
+        assert_eq!(results[0].code, ResultCode::Success);
+        assert!(dir.path().join("beach.jpg").exists());
+        assert_eq!(results[1].code, ResultCode::Success);
+        assert_eq!(results[2].code, ResultCode::DuplicateTarget);
+        assert!(c.exists());
+    }
+
+    #[test]
+    fn test_apply_names_validates_names() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("draft.txt");
+        File::create(&file_path).unwrap();
+
+        let results = apply_names(
+            vec![(file_path.to_str().unwrap().into(), "a/b.txt".into())],
+            None,
+        );
+
+        assert_eq!(results[0].code, ResultCode::InvalidName);
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_apply_names_braces_verbatim() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+        let pairs = vec![
+            (a.to_str().unwrap().to_string(), "x{y}.txt".to_string()),
+            (b.to_str().unwrap().to_string(), "{date}.txt".to_string()),
+        ];
+
+        let results = apply_names(pairs, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("x{y}.txt"));
+        assert_eq!(results[1].new_name.as_deref(), Some("{date}.txt"));
+        assert!(dir.path().join("{date}.txt").exists());
+    }
+
+    #[test]
+    fn test_add_repad_trailing() {
+        let dir = tempdir().unwrap();
+        for (old, position, expected) in [
+            ("ep5.mkv", Position::Start, "S1_ep005.mkv"),
+            ("ep00042.mkv", Position::End, "ep042_old.mkv"),
+            ("ep1234.mkv", Position::Start, "S1_ep1234.mkv"),
+            ("notes.txt", Position::End, "notes_old.txt"),
+        ] {
+            let file_path = dir.path().join(old);
+            File::create(&file_path).unwrap();
+            let text = match position {
+                Position::Start => "S1_",
+                Position::End => "_old",
+            };
+            let cmd = RenameCommand::Add {
+                text: text.into(),
+                position,
+                repad_trailing: Some(3),
+            };
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(res.new_name.as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_add_checksum_max_bytes() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("video.mp4");
+        fs::write(&file_path, [0u8; 2048]).unwrap();
+
+        let cmd = RenameCommand::AddChecksum {
+            algo: ChecksumAlgo::Crc32,
+            length: 8,
+            max_bytes: Some(1024),
+            timeout_ms: None,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::FileTooLarge);
+        assert_eq!(res.status, "File too large");
+        assert!(file_path.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_add_checksum_read_timeout() {
+        use std::os::unix::ffi::OsStrExt;
+
+        // Opening a FIFO with no writer blocks, like a stalled share
+        let dir = tempdir().unwrap();
+        let fifo = dir.path().join("stalled.bin");
+        let c_path = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        let cmd = RenameCommand::AddChecksum {
+            algo: ChecksumAlgo::Sha1,
+            length: 8,
+            max_bytes: None,
+            timeout_ms: Some(50),
+        };
+        let res = handle_rename(fifo.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::ReadTimeout);
+        assert_eq!(res.status, "Read timeout");
+        // Unblock the worker so it doesn't outlive the test
+        let _ = fs::OpenOptions::new().write(true).open(&fifo);
+    }
+
+    #[test]
+    fn test_add_checksum_limits_by_default() {
+        let parse = |config: serde_json::Value| {
+            let json = serde_json::json!({"mode": "AddChecksum", "config": config});
+            match serde_json::from_value(json).unwrap() {
+                RenameCommand::AddChecksum {
+                    max_bytes,
+                    timeout_ms,
+                    ..
+                } => (max_bytes, timeout_ms),
+                _ => unreachable!(),
+            }
+        };
+
+        let limits = parse(serde_json::json!({"algo": "sha1", "length": 8}));
+        assert_eq!(limits, (Some(4 << 30), Some(60_000)));
+        let unlimited = serde_json::json!({
+            "algo": "sha1",
+            "length": 8,
+            "max_bytes": null,
+            "timeout_ms": null,
+        });
+        assert_eq!(parse(unlimited), (None, None));
+    }
+
+    #[test]
+    fn test_rename_batch_precondition_size() {
+        let dir = tempdir().unwrap();
+        let big = dir.path().join("big.bin");
+        let small = dir.path().join("small.bin");
+        fs::write(&big, [0u8; 2048]).unwrap();
+        fs::write(&small, [0u8; 16]).unwrap();
+        let options = RenameOptions {
+            precondition: Some(Precondition::MinSize(1024)),
+            ..Default::default()
+        };
+        let cmd = RenameCommand::Add {
+            text: "_large".into(),
+            position: Position::End,
+            repad_trailing: None,
+        };
+
+        let results = handle_rename_batch(
+            vec![big.to_str().unwrap().into(), small.to_str().unwrap().into()],
+            cmd,
+            Some(options),
+        );
+
+        assert_eq!(results[0].code, ResultCode::Success);
+        assert_eq!(results[1].code, ResultCode::PreconditionFailed);
+        assert_eq!(results[1].status, "Precondition not met");
+        assert!(small.exists());
+    }
+
+    #[test]
+    fn test_rename_precondition_older_than() {
+        let dir = tempdir().unwrap();
+        let old = dir.path().join("old.txt");
+        let new = dir.path().join("new.txt");
+        let jan_2020 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+        File::create(&old).unwrap().set_modified(jan_2020).unwrap();
+        File::create(&new).unwrap();
+        let options = RenameOptions {
+            precondition: Some(Precondition::OlderThan(1_600_000_000)),
+            ..Default::default()
+        };
+        let cmd = RenameCommand::Add {
+            text: "archive_".into(),
+            position: Position::Start,
+            repad_trailing: None,
+        };
+
+        let results = handle_rename_batch(
+            vec![old.to_str().unwrap().into(), new.to_str().unwrap().into()],
+            cmd,
+            Some(options),
+        );
+
+        assert_eq!(results[0].new_name.as_deref(), Some("archive_old.txt"));
+        assert_eq!(results[1].code, ResultCode::PreconditionFailed);
+    }
+
+    #[test]
+    fn test_rename_ja_punctuation() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("「テスト」、完了。.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::JaPunctuation {
+            to: WidthMode::Hankaku,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+        assert_eq!(res.new_name.as_deref(), Some("｢テスト｣､完了｡.txt"));
+
+        let cmd = RenameCommand::JaPunctuation {
+            to: WidthMode::Zenkaku,
+        };
+        let half = dir.path().join("｢テスト｣､完了｡.txt");
+        let res = handle_rename(half.to_str().unwrap().into(), cmd, None);
+        assert_eq!(res.new_name.as_deref(), Some("「テスト」、完了。.txt"));
+    }
+
+    #[test]
+    fn test_rename_batch_reverse_order() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["img1.png", "img2.png"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let cmd = || RenameCommand::ShiftNumber { delta: 1, pad: 0 };
+
+        // Forward, img2 is still there when img1 wants it
+        let results = handle_rename_batch(paths.clone(), cmd(), None);
+        assert_eq!(results[0].code, ResultCode::TargetExists);
+        fs::rename(dir.path().join("img3.png"), &paths[1]).unwrap();
+
+        let options = RenameOptions {
+            reverse_order: true,
+            ..Default::default()
+        };
+        let results = handle_rename_batch(paths.clone(), cmd(), Some(options));
+        assert_eq!(results[0].new_name.as_deref(), Some("img2.png"));
+        assert_eq!(results[1].new_name.as_deref(), Some("img3.png"));
+        assert!(!dir.path().join("img1.png").exists());
+    }
+
+    #[test]
+    fn test_rename_wrap() {
+        let dir = tempdir().unwrap();
+        for (open, close, expected) in [
+            ("[", "]", "[title].txt"),
+            ("【", "】", "【title】.txt"),
+            ("«« ", " »»", "«« title »».txt"),
+        ] {
+            let file_path = dir.path().join("title.txt");
+            File::create(&file_path).unwrap();
+            let cmd = RenameCommand::Wrap {
+                open: open.into(),
+                close: close.into(),
+            };
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(res.new_name.as_deref(), Some(expected));
+            fs::remove_file(dir.path().join(expected)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rename_wrap_rejects_illegal_wrapper() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("title.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::Wrap {
+            open: "/".into(),
+            close: "/".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::InvalidInput);
+        assert_eq!(res.status, "Illegal characters in wrapper: \"/\"");
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_rename_batch_serial_pad_too_narrow() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = (0..3)
+            .map(|i| {
+                let p = dir.path().join(format!("f{}.txt", i));
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let serial = |number| RenameCommand::Serial {
+            prefix: "page_".into(),
+            suffix: "".into(),
+            number,
+            pad: 1,
+            keep_ext: true,
+            keep_original: false,
+            pad_side: PadSide::Left,
+            pad_char: '0',
+            sign: SignMode::Negative,
+            radix: Radix::Decimal,
+        };
+
+        let results = handle_rename_batch(paths.clone(), serial(8), None);
+        assert!(results.iter().all(|r| r.code == ResultCode::InvalidInput));
+        assert_eq!(
+            results[0].status,
+            "Serial pad 1 is too narrow for numbers up to 10"
+        );
+        assert!(dir.path().join("f0.txt").exists());
+
+        let verdicts = analyze_batch(paths, serial(8), None);
+        assert!(verdicts[0].status.contains("too narrow"));
+    }
+
+    #[test]
+    fn test_check_serial_batch_repeats() {
+        let cmd = RenameCommand::Serial {
+            prefix: "".into(),
+            suffix: "".into(),
+            number: 1,
+            pad: 3,
+            keep_ext: true,
+            keep_original: false,
+            pad_side: PadSide::Right,
+            pad_char: '0',
+            sign: SignMode::Negative,
+            radix: Radix::Decimal,
+        };
+        assert!(check_serial_batch(&cmd, 9).is_ok());
+        let err = check_serial_batch(&cmd, 10).unwrap_err();
+        assert_eq!(err.message, "Serial numbers repeat: 100");
+    }
+
+    #[test]
+    fn test_list_with_preview() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("b.txt")).unwrap();
+        File::create(dir.path().join("B.TXT.bak")).unwrap();
+        File::create(dir.path().join("a.TXT")).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        File::create(dir.path().join("sub").join("c.txt")).unwrap();
+        let cmd = || RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+
+        let flat = list_with_preview(dir.path().to_str().unwrap().into(), cmd(), false);
+        let names: Vec<&str> = flat.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["B.TXT.bak", "a.TXT", "b.txt"]);
+        assert_eq!(flat[0].code, ResultCode::Unchanged);
+        assert_eq!(flat[1].new_name.as_deref(), Some("A.TXT"));
+        assert_eq!(flat[1].code, ResultCode::Success);
+        assert_eq!(flat[2].new_name.as_deref(), Some("B.txt"));
+
+        let deep = list_with_preview(dir.path().to_str().unwrap().into(), cmd(), true);
+        assert_eq!(deep.len(), 4);
+        assert_eq!(deep[3].new_name.as_deref(), Some("C.txt"));
+        assert!(dir.path().join("a.TXT").exists());
+    }
+
+    #[test]
+    fn test_list_with_preview_missing_dir() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("gone");
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+
+        let res = list_with_preview(missing.to_str().unwrap().into(), cmd, false);
+
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].code, ResultCode::IoError);
+    }
+
+    #[test]
+    fn test_rename_replace_preserve_ext() {
+        let dir = tempdir().unwrap();
+        let cmd = |preserve_ext| RenameCommand::Replace {
+            from: r"_draft\.(\w+)".into(),
+            to: ".final.${1}x".into(),
+            use_regex: true,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+
+        let file_path = dir.path().join("essay_draft.doc");
+        File::create(&file_path).unwrap();
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd(false), None);
+        assert_eq!(res.new_name.as_deref(), Some("essay.final.docx"));
+
+        let file_path = dir.path().join("letter_draft.doc");
+        File::create(&file_path).unwrap();
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd(true), None);
+        assert_eq!(res.new_name.as_deref(), Some("letter.final.doc"));
+    }
+
+    /// A silent one-sample 8 kHz mono WAV file.
+    fn silent_wav() -> Vec<u8> {
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&38u32.to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&16000u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&2u32.to_le_bytes());
+        wav.extend_from_slice(&[0, 0]);
+        wav
+    }
+
+    #[test]
+    fn test_rename_media_tags() {
+        use lofty::config::WriteOptions;
+        use lofty::prelude::*;
+        use lofty::tag::{Tag, TagType};
+
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("track.wav");
+        fs::write(&file_path, silent_wav()).unwrap();
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.set_title("AC/DC: Live?".into());
+        tag.set_track(7);
+        tag.save_to_path(&file_path, WriteOptions::default())
+            .unwrap();
+
+        let cmd = RenameCommand::MediaTags {
+            pattern: "{track} - {title} ({album})".into(),
+            placeholder: "Unknown".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "7 - AC_DC_ Live_ (Unknown).wav");
+    }
+
+    #[test]
+    fn test_rename_media_tags_no_tags() {
+        let dir = tempdir().unwrap();
+        let untagged = dir.path().join("plain.wav");
+        fs::write(&untagged, silent_wav()).unwrap();
+        let text = dir.path().join("notes.txt");
+        fs::write(&text, "not audio").unwrap();
+
+        for path in [untagged, text] {
+            let cmd = RenameCommand::MediaTags {
+                pattern: "{title}".into(),
+                placeholder: "".into(),
+            };
+            let res = handle_rename(path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(res.code, ResultCode::NoTags);
+            assert_eq!(res.status, "No tags");
+        }
+    }
+
+    #[test]
+    fn test_rename_batch_time_order_serial() {
+        let dir = tempdir().unwrap();
+        let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        // Deliberately out of name order; b and a share a time
+        let paths: Vec<String> = [("c.jpg", 0), ("b.jpg", 60), ("a.jpg", 60), ("z.jpg", 30)]
+            .iter()
+            .map(|(name, offset)| {
+                let p = dir.path().join(name);
+                File::create(&p)
+                    .unwrap()
+                    .set_modified(base + Duration::from_secs(*offset))
+                    .unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let cmd = RenameCommand::TimeOrderSerial {
+            prefix: "frame_".into(),
+            pad: 3,
+            keep_ext: true,
+        };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        let names: Vec<&str> = results
+            .iter()
+            .map(|r| r.new_name.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "frame_001.jpg",
+                "frame_004.jpg",
+                "frame_003.jpg",
+                "frame_002.jpg"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rename_trim_relative() {
+        let dir = tempdir().unwrap();
+        for (keep, include_marker, expected) in [
+            (MarkerSide::After, false, "realname.txt"),
+            (MarkerSide::After, true, "-JUNK-realname.txt"),
+            (MarkerSide::Before, false, "prefix.txt"),
+            (MarkerSide::Before, true, "prefix-JUNK-.txt"),
+        ] {
+            let file_path = dir.path().join("prefix-JUNK-realname.txt");
+            File::create(&file_path).unwrap();
+            let cmd = RenameCommand::TrimRelative {
+                marker: "-JUNK-".into(),
+                keep,
+                include_marker,
+            };
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(res.new_name.as_deref(), Some(expected));
+            fs::remove_file(dir.path().join(expected)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rename_trim_relative_marker_not_found() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("realname.txt");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::TrimRelative {
+            marker: "-JUNK-".into(),
+            keep: MarkerSide::After,
+            include_marker: false,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::NoMatch);
+        assert_eq!(res.status, "Marker not found");
+    }
+
+    #[test]
+    fn test_rename_batch_align_numbers() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["take2.wav", "take15_v3.wav", "take100.wav", "intro.wav"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let results = handle_rename_batch(paths, RenameCommand::AlignNumbers, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("take002.wav"));
+        assert_eq!(results[1].new_name.as_deref(), Some("take015_v3.wav"));
+        assert_eq!(results[2].code, ResultCode::Unchanged);
+        assert_eq!(results[3].code, ResultCode::NoNumberFound);
+        assert_eq!(results[3].status, "No number found");
+    }
+
+    #[test]
+    fn test_rename_batch_rebase_numbers() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = [
+            "shot_0009.exr",
+            "shot_0001.exr",
+            "shot_0005.exr",
+            "notes.txt",
+        ]
+        .iter()
+        .map(|name| {
+            let p = dir.path().join(name);
+            File::create(&p).unwrap();
+            p.to_str().unwrap().to_string()
+        })
+        .collect();
+
+        let cmd = RenameCommand::RebaseNumbers { new_start: 10 };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("shot_0018.exr"));
+        assert_eq!(results[1].new_name.as_deref(), Some("shot_0010.exr"));
+        assert_eq!(results[2].new_name.as_deref(), Some("shot_0014.exr"));
+        assert_eq!(results[3].code, ResultCode::NoNumberFound);
+    }
+
+    #[test]
+    fn test_describe() {
+        let cmd = RenameCommand::Trim {
+            count: 2,
+            position: Position::Start,
+        };
+        assert_eq!(describe(&cmd), r#"Trim {"count":2,"position":"start"}"#);
+        assert_eq!(describe(&RenameCommand::AlignNumbers), "AlignNumbers");
+    }
+
+    /// Name-only commands run through `compute_new_name` straight from a
+    /// table; no file is touched.
+    #[test]
+    fn test_compute_new_name_table() {
+        let cases = [
+            (
+                RenameCommand::Trim {
+                    count: 4,
+                    position: Position::Start,
+                },
+                "IMG_0001.jpg",
+                "0001.jpg",
+            ),
+            (
+                RenameCommand::Wrap {
+                    open: "(".into(),
+                    close: ")".into(),
+                },
+                "draft.md",
+                "(draft).md",
+            ),
+            (
+                RenameCommand::ShiftNumber { delta: -1, pad: 2 },
+                "ep10.mkv",
+                "ep09.mkv",
+            ),
+            (
+                RenameCommand::TrimRelative {
+                    marker: " - ".into(),
+                    keep: MarkerSide::After,
+                    include_marker: false,
+                },
+                "Artist - Song.mp3",
+                "Song.mp3",
+            ),
+            (
+                RenameCommand::JaPunctuation {
+                    to: WidthMode::Hankaku,
+                },
+                "「題」.txt",
+                "｢題｣.txt",
+            ),
+            (
+                RenameCommand::DedupeExtension,
+                "report.pdf.pdf",
+                "report.pdf",
+            ),
+            (RenameCommand::DedupeExtension, "IMG.JPG.jpg", "IMG.jpg"),
+            (RenameCommand::DedupeExtension, "a.pdf.pdf.pdf", "a.pdf"),
+            (
+                RenameCommand::DedupeExtension,
+                "archive.tar.tar.gz",
+                "archive.tar.tar.gz",
+            ),
+            (RenameCommand::DedupeExtension, "a.txt.bak", "a.txt.bak"),
+            (
+                RenameCommand::HanConvert {
+                    to: HanScript::Traditional,
+                },
+                "头发_2024.txt",
+                "頭髮_2024.txt",
+            ),
+            (
+                RenameCommand::HanConvert {
+                    to: HanScript::Simplified,
+                },
+                "歷史 Notes.docx",
+                "历史 Notes.docx",
+            ),
+            (
+                RenameCommand::SeparateAlnum {
+                    separator: "_".into(),
+                },
+                "abc123def.txt",
+                "abc_123_def.txt",
+            ),
+            (
+                RenameCommand::SeparateAlnum {
+                    separator: "_".into(),
+                },
+                "2024report7.pdf",
+                "2024_report_7.pdf",
+            ),
+            (
+                RenameCommand::SeparateAlnum {
+                    separator: "_".into(),
+                },
+                "abc_123-def.txt",
+                "abc_123-def.txt",
+            ),
+            (
+                RenameCommand::SeparateAlnum {
+                    separator: " ".into(),
+                },
+                "第3章.md",
+                "第 3 章.md",
+            ),
+            (
+                RenameCommand::TrimSeparators {
+                    chars: vec!['_', '-'],
+                    start: true,
+                    end: true,
+                },
+                "__report-_.txt",
+                "report.txt",
+            ),
+            (
+                RenameCommand::TrimSeparators {
+                    chars: vec!['_'],
+                    start: false,
+                    end: true,
+                },
+                "_draft_.md",
+                "_draft.md",
+            ),
+            (RenameCommand::DedupeExtension, ".pdf.pdf", ".pdf.pdf"),
+        ];
+        for (cmd, old_name, expected) in cases {
+            let path = Path::new(old_name);
+            let stem = path.file_stem().unwrap().to_str().unwrap();
+            let ext = path.extension().unwrap().to_str().unwrap();
+            let prepared = PreparedCommand::new(&cmd);
+            let new_name = compute_new_name(path, old_name, stem, ext, &prepared, 0).ok();
+            assert_eq!(new_name.as_deref(), Some(expected), "{}", describe(&cmd));
+        }
+    }
+
+    #[test]
+    fn test_rename_recursive_flatten_name() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        File::create(nested.join("img.jpg")).unwrap();
+        File::create(dir.path().join("top.jpg")).unwrap();
+        let root = dir.path().to_str().unwrap().to_string();
+
+        let cmd = RenameCommand::FlattenName {
+            root: root.clone(),
+            separator: "__".into(),
+        };
+        let results = handle_rename_recursive(root, cmd, None, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("a__b__img.jpg"));
+        assert!(nested.join("a__b__img.jpg").exists());
+        // Nothing to prefix at the root itself
+        assert_eq!(results[1].code, ResultCode::Unchanged);
+    }
+
+    #[test]
+    fn test_rename_flatten_name_outside_root() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("img.jpg");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::FlattenName {
+            root: "/nonexistent-root".into(),
+            separator: "__".into(),
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::InvalidInput);
+        assert_eq!(res.status, "Path is not under root");
+    }
+
+    #[test]
+    fn test_rename_strip_bidi() {
+        let dir = tempdir().unwrap();
+        // Displays as "invoiceexe.pdf" with the RLO in place
+        let file_path = dir.path().join("invoice\u{202E}fdp\u{200F}.exe");
+        File::create(&file_path).unwrap();
+
+        let res = handle_rename(
+            file_path.to_str().unwrap().into(),
+            RenameCommand::StripBidi,
+            None,
+        );
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "invoicefdp.exe");
+    }
+
+    #[test]
+    fn test_rename_batch_flag_duplicate_content() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        let c = dir.path().join("c.txt");
+        fs::write(&a, "same").unwrap();
+        fs::write(&b, "same").unwrap();
+        fs::write(&c, "diff").unwrap();
+        let paths: Vec<String> = [&a, &b, &c]
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
+        let options = RenameOptions {
+            flag_duplicate_content: true,
+            ..Default::default()
+        };
+        let cmd = RenameCommand::Add {
+            text: "_x".into(),
+            position: Position::End,
+            repad_trailing: None,
+        };
+
+        let results = handle_rename_batch(paths.clone(), cmd, Some(options));
+
+        assert!(results.iter().all(|r| r.code == ResultCode::Success));
+        assert_eq!(
+            results[0].warning,
+            Some(format!("Duplicate content of {}", paths[1]))
+        );
+        assert_eq!(
+            results[1].warning,
+            Some(format!("Duplicate content of {}", paths[0]))
+        );
+        assert_eq!(results[2].warning, None);
+    }
+
+    #[test]
+    fn test_duplicate_content_skips_oversized() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.bin", "b.bin"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                fs::write(&p, [7u8; 2048]).unwrap();
+                p
+            })
+            .collect();
+
+        assert_eq!(duplicate_content(&paths, None, None), [Some(1), Some(0)]);
+        assert_eq!(duplicate_content(&paths, Some(1024), None), [None, None]);
+    }
+
+    #[test]
+    fn test_rename_replace_literal_replacement() {
+        let dir = tempdir().unwrap();
+        let cmd = |literal_replacement| RenameCommand::Replace {
+            from: r"PRICE".into(),
+            to: "$3.50".into(),
+            use_regex: true,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement,
+            preserve_case: false,
+        };
+
+        let file_path = dir.path().join("coffee_PRICE.txt");
+        File::create(&file_path).unwrap();
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd(true), None);
+        assert_eq!(res.new_name.as_deref(), Some("coffee_$3.50.txt"));
+
+        // Expanded, `$3` is an empty group reference
+        let file_path = dir.path().join("tea_PRICE.txt");
+        File::create(&file_path).unwrap();
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd(false), None);
+        assert_eq!(res.new_name.as_deref(), Some("tea_.50.txt"));
+    }
+
+    #[test]
+    fn test_rename_replace_preserve_case() {
+        let dir = tempdir().unwrap();
+        let cmd = || RenameCommand::Replace {
+            from: "color".into(),
+            to: "colour".into(),
+            use_regex: false,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: true,
+        };
+
+        for (name, expected) in [
+            ("color_chart.txt", "colour_chart.txt"),
+            ("COLOR_CHART.txt", "COLOUR_CHART.txt"),
+            ("Color Chart.txt", "Colour Chart.txt"),
+        ] {
+            let file_path = dir.path().join(name);
+            File::create(&file_path).unwrap();
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd(), None);
+            assert_eq!(res.new_name.as_deref(), Some(expected), "{}", name);
+        }
+
+        // A mixed-case match keeps `to` as written
+        assert_eq!(match_case("cOLor", "colour"), "colour");
+        assert_eq!(match_case("cOLor", "Colour"), "Colour");
+    }
+
+    #[test]
+    fn test_rename_add_dimensions() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("wall.png");
+        image::RgbImage::new(32, 18).save(&file_path).unwrap();
+
+        let cmd = RenameCommand::AddDimensions {
+            separator: "_".into(),
+            position: Position::End,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "wall_32x18.png");
+    }
+
+    #[test]
+    fn test_rename_add_dimensions_not_an_image() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("fake.jpg");
+        fs::write(&file_path, "plain text").unwrap();
+
+        let cmd = RenameCommand::AddDimensions {
+            separator: "_".into(),
+            position: Position::Start,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.code, ResultCode::NotAnImage);
+        assert_eq!(res.status, "Not an image");
+    }
+
+    #[test]
+    fn test_rename_collision_suffix_template() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("report.txt")).unwrap();
+        File::create(dir.path().join("report-copy1.txt")).unwrap();
+        let file_path = dir.path().join("draft.txt");
+        File::create(&file_path).unwrap();
+        let options = RenameOptions {
+            collision: Collision::Suffix {
+                template: "-copy{n}".into(),
+            },
+            ..Default::default()
+        };
+        let cmd = RenameCommand::Fixed {
+            name: "report".into(),
+            keep_ext: true,
+            expand_tokens: false,
+        };
+
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, Some(options));
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.as_deref(), Some("report-copy2.txt"));
+        assert!(dir.path().join("report-copy2.txt").exists());
+    }
+
+    #[test]
+    fn test_rename_collision_suffix_template_needs_counter() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("draft.txt");
+        File::create(&file_path).unwrap();
+        for (template, status) in [
+            ("_copy", "Suffix template must contain {n}"),
+            ("/{n}", "Illegal characters in suffix template: \"/\""),
+        ] {
+            let options = RenameOptions {
+                collision: Collision::Suffix {
+                    template: template.into(),
+                },
+                ..Default::default()
+            };
+            let cmd = RenameCommand::Fixed {
+                name: "final".into(),
+                keep_ext: true,
+                expand_tokens: false,
+            };
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, Some(options));
+            assert_eq!(res.code, ResultCode::InvalidInput);
+            assert_eq!(res.status, status);
+        }
+    }
+
+    #[test]
+    fn test_rename_batch_ext_case_for() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["scan.tif", "raw.Dng", "photo.jpg", "README"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let cmd = RenameCommand::ExtCaseFor {
+            extensions: vec!["tif".into(), ".DNG".into()],
+            mode: CaseMode::Upper,
+        };
+        let results = handle_rename_batch(paths, cmd, None);
+
+        assert_eq!(results[0].new_name.as_deref(), Some("scan.TIF"));
+        assert_eq!(results[1].new_name.as_deref(), Some("raw.DNG"));
+        assert_eq!(results[2].status, "Unchanged");
+        assert_eq!(results[3].status, "Unchanged");
+        assert!(dir.path().join("photo.jpg").exists());
+    }
+
+    #[test]
+    fn test_rename_recursive_max_depth() {
+        let dir = tempdir().unwrap();
+        let deep = dir.path().join("one").join("two");
+        fs::create_dir_all(&deep).unwrap();
+        for file in [
+            dir.path().join("root.txt"),
+            dir.path().join("one").join("mid.txt"),
+            deep.join("deep.txt"),
+        ] {
+            File::create(file).unwrap();
+        }
+        let cmd = RenameCommand::Case {
+            mode: CaseMode::Upper,
+            split: ExtSplit::LastDot,
+            only_if: CaseCondition::Always,
+            include_ext: false,
+        };
+
+        let results =
+            handle_rename_recursive(dir.path().to_str().unwrap().into(), cmd, None, Some(1));
+
+        assert_eq!(results.len(), 2);
+        assert!(dir.path().join("ROOT.txt").exists());
+        assert!(dir.path().join("one").join("MID.txt").exists());
+        assert!(deep.join("deep.txt").exists());
+    }
+
+    #[test]
+    fn test_remove_words() {
+        let words = vec!["the".to_string(), "a".to_string(), "an".to_string()];
+        let whole = remove_words_regex(&words, true, true).unwrap();
+        assert_eq!(
+            remove_words("The Lord of the Rings - A Journey", &whole, true),
+            "Lord of Rings - Journey"
+        );
+        assert_eq!(
+            remove_words("the_theme_an_anthem", &whole, true),
+            "theme_anthem"
+        );
+
+        let partial = remove_words_regex(&["_draft".to_string()], false, false).unwrap();
+        assert_eq!(
+            remove_words("essay_draft_v2_draft", &partial, false),
+            "essay_v2"
+        );
+        assert!(remove_words_regex(&[String::new()], true, false).is_err());
+    }
+
+    #[test]
+    fn test_rename_remove_words() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("The Matrix (1999).mkv");
+        File::create(&file_path).unwrap();
+
+        let cmd = RenameCommand::RemoveWords {
+            words: vec!["the".into()],
+            whole_word: true,
+            ignore_case: true,
+        };
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+
+        assert_eq!(res.status, "Success");
+        assert_eq!(res.new_name.unwrap(), "Matrix (1999).mkv");
+    }
+
+    #[test]
+    fn test_normalizing_commands_are_idempotent() {
+        let cases = [
+            (
+                RenameCommand::Case {
+                    mode: CaseMode::Lower,
+                    split: ExtSplit::LastDot,
+                    only_if: CaseCondition::Always,
+                    include_ext: false,
+                },
+                "My Photo.JPG",
+            ),
+            (
+                RenameCommand::Convert {
+                    mode: WidthMode::Hankaku,
+                    split: ExtSplit::LastDot,
+                },
+                "ＡＢＣ　１２３.txt",
+            ),
+            (
+                RenameCommand::Slugify {
+                    separator: "-".into(),
+                    max_length: Some(12),
+                },
+                "Été à Paris, 2024!.jpg",
+            ),
+            (
+                RenameCommand::Compatibility {
+                    form: CompatibilityForm::Nfkc,
+                },
+                "①ﬁｌｅ.txt",
+            ),
+            (
+                RenameCommand::CollapseRepeats {
+                    chars: vec!['_', ' '],
+                },
+                "a__b   c.txt",
+            ),
+            (
+                RenameCommand::TrimWhitespace {
+                    start: true,
+                    end: true,
+                },
+                "  padded \u{3000}.txt",
+            ),
+            (
+                RenameCommand::StripEmoji {
+                    replacement: "_".into(),
+                },
+                "trip🏖️2024.jpg",
+            ),
+            (RenameCommand::StripBidi, "a\u{202E}b.txt"),
+            (
+                RenameCommand::StripControlChars {
+                    replacement: "_".into(),
+                },
+                "tab\there.txt",
+            ),
+            (
+                RenameCommand::JaPunctuation {
+                    to: WidthMode::Hankaku,
+                },
+                "「題」.txt",
+            ),
+            (
+                RenameCommand::RemoveWords {
+                    words: vec!["the".into()],
+                    whole_word: true,
+                    ignore_case: true,
+                },
+                "The The Band.mp3",
+            ),
+            (RenameCommand::EnsureExt { ext: "txt".into() }, "notes"),
+        ];
+        for (cmd, name) in cases {
+            let dir = tempdir().unwrap();
+            let file_path = dir.path().join(name);
+            File::create(&file_path).unwrap();
+
+            let again: RenameCommand =
+                serde_json::from_value(serde_json::to_value(&cmd).unwrap()).unwrap();
+            let first = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(first.code, ResultCode::Success, "{}", name);
+            let renamed = dir.path().join(first.new_name.unwrap());
+            let second = handle_rename(renamed.to_str().unwrap().into(), again, None);
+            assert_eq!(second.code, ResultCode::Unchanged, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_sort_into_year_folder() {
+        let dir = tempdir().unwrap();
+        let dated = dir.path().join("2023-05-01_note.txt");
+        let undated = dir.path().join("note.txt");
+        File::create(&dated).unwrap();
+        File::create(&undated).unwrap();
+        let cmd = RenameCommand::SortInto {
+            pattern: r"(\d{4})-\d{2}-\d{2}".into(),
+            folder: "$1".into(),
+        };
+
+        let results = handle_rename_batch(
+            vec![
+                dated.to_str().unwrap().into(),
+                undated.to_str().unwrap().into(),
+            ],
+            cmd,
+            None,
+        );
+        assert_eq!(results[0].code, ResultCode::Success);
+        assert_eq!(
+            results[0].new_name.as_deref(),
+            Some(
+                Path::new("2023")
+                    .join("2023-05-01_note.txt")
+                    .to_str()
+                    .unwrap()
+            )
+        );
+        assert!(dir.path().join("2023").join("2023-05-01_note.txt").exists());
+        assert_eq!(results[1].code, ResultCode::NoMatch);
+        assert!(undated.exists());
+    }
+
+    #[test]
+    fn test_sort_into_rejects_escaping_folder() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("up_file.txt");
+        File::create(&file_path).unwrap();
+        let cmd = RenameCommand::SortInto {
+            pattern: r"^(\w+?)_".into(),
+            folder: "$1/..".into(),
+        };
+
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+        assert_eq!(res.code, ResultCode::InvalidName);
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_preview_grouped() {
+        let dir = tempdir().unwrap();
+        for name in ["a_v1.txt", "b_v1.txt", "c_v2.txt", "a_v2.txt"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let paths: Vec<String> = ["a_v1.txt", "b_v1.txt", "c_v2.txt", "missing_v1.txt"]
+            .iter()
+            .map(|name| dir.path().join(name).to_str().unwrap().to_string())
+            .collect();
+        let cmd = RenameCommand::Replace {
+            from: "v1".into(),
+            to: "v2".into(),
+            use_regex: false,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+
+        let grouped = preview_grouped(paths, cmd, None);
+        let names = |bucket: &[FilePreview]| -> Vec<String> {
+            bucket.iter().map(|p| p.name.clone()).collect()
+        };
+        assert_eq!(names(&grouped.will_rename), ["b_v1.txt"]);
+        assert_eq!(grouped.will_rename[0].new_name.as_deref(), Some("b_v2.txt"));
+        assert_eq!(names(&grouped.no_op), ["c_v2.txt"]);
+        assert_eq!(names(&grouped.conflicts), ["a_v1.txt"]);
+        assert_eq!(names(&grouped.errors), ["missing_v1.txt"]);
+        assert!(dir.path().join("b_v1.txt").exists());
+    }
+
+    #[test]
+    fn test_restore_from_sidecars() {
+        let dir = tempdir().unwrap();
+        let options = RenameOptions {
+            write_sidecar: true,
+            ..Default::default()
+        };
+        let original = dir.path().join("a.txt");
+        File::create(&original).unwrap();
+
+        let mut current = original.clone();
+        for name in ["b", "c"] {
+            let cmd = RenameCommand::Fixed {
+                name: name.into(),
+                keep_ext: true,
+                expand_tokens: false,
+            };
+            let res = handle_rename(current.to_str().unwrap().into(), cmd, Some(options.clone()));
+            assert_eq!(res.code, ResultCode::Success);
+            current = dir.path().join(res.new_name.unwrap());
+        }
+        // Renaming again moved the sidecar along with the first original name
+        assert!(!dir.path().join("b.txt.origname").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("c.txt.origname")).unwrap(),
+            "a.txt"
+        );
+
+        let results = restore_from_sidecars(dir.path().to_str().unwrap().into());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].code, ResultCode::Success);
+        assert_eq!(results[0].new_name.as_deref(), Some("a.txt"));
+        assert!(original.exists());
+        assert!(!dir.path().join("c.txt.origname").exists());
+    }
+
+    #[test]
+    fn test_sidecars_left_out_and_kept() {
+        let dir = tempdir().unwrap();
+        let options = RenameOptions {
+            write_sidecar: true,
+            ..Default::default()
+        };
+        File::create(dir.path().join("a.txt")).unwrap();
+        fs::write(dir.path().join("b.txt.origname"), "stale.txt").unwrap();
+        let cmd = || RenameCommand::Fixed {
+            name: "b".into(),
+            keep_ext: true,
+            expand_tokens: false,
+        };
+
+        let results = handle_rename_recursive(
+            dir.path().to_str().unwrap().into(),
+            cmd(),
+            Some(options),
+            None,
+        );
+
+        // Only the file was renamed; the sidecar in the way was kept
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].code, ResultCode::Success);
+        assert!(results[0]
+            .warning
+            .as_deref()
+            .unwrap()
+            .starts_with("Sidecar write failed"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("b.txt.origname")).unwrap(),
+            "stale.txt"
+        );
+    }
+
+    #[test]
+    fn test_count_matches() {
+        let paths = vec![
+            "/photos/IMG_0001_0002.jpg".to_string(),
+            "/photos/notes.txt".to_string(),
+        ];
+        let counts = count_matches(paths, r"\d+".into()).unwrap();
+        assert_eq!(
+            counts,
+            [
+                ("/photos/IMG_0001_0002.jpg".to_string(), 2),
+                ("/photos/notes.txt".to_string(), 0),
+            ]
+        );
+
+        let err = count_matches(vec!["a.txt".into()], "(".into()).unwrap_err();
+        assert!(err.starts_with("Regex error"), "{}", err);
+    }
+
+    #[test]
+    fn test_rename_durable() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("data.csv");
+        File::create(&file_path).unwrap();
+        let options = RenameOptions {
+            durable: true,
+            ..Default::default()
+        };
+        let cmd = RenameCommand::Add {
+            text: "_final".into(),
+            position: Position::End,
+            repad_trailing: None,
+        };
+
+        let res = handle_rename(file_path.to_str().unwrap().into(), cmd, Some(options));
+        assert_eq!(res.code, ResultCode::Success);
+        assert_eq!(res.warning, None);
+        assert!(dir.path().join("data_final.csv").exists());
+    }
+
+    #[test]
+    fn test_record_rename_sync_and_journal_fail() {
+        let dir = tempdir().unwrap();
+        let old_path = dir.path().join("data.csv");
+        let new_path = dir.path().join("data_final.csv");
+        File::create(&new_path).unwrap();
+        // A directory can't be appended to
+        let options = RenameOptions {
+            durable: true,
+            journal_path: Some(dir.path().to_str().unwrap().into()),
+            ..Default::default()
+        };
+        let prepared = PreparedOptions::new(&options).unwrap();
+        let diff = NameDiff::between("data.csv", "data_final.csv");
+        let mut res = RenameResult::success("data.csv".into(), "data_final.csv".into(), diff);
+
+        let refuse = |_: &Path| Err(io::Error::other("refused"));
+        record_rename(&mut res, &old_path, &new_path, &prepared, refuse);
+
+        let warning = res.warning.unwrap();
+        assert!(warning.starts_with("Sync failed: "), "{}", warning);
+        assert!(warning.contains("; Journal write failed: "), "{}", warning);
+    }
+
+    #[test]
+    fn test_preview_by_risk() {
+        let dir = tempdir().unwrap();
+        let names = ["d.txt", "c_v1", "b_v1.txt", "a_v1.txt", "a_v2"];
+        for name in names {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let paths: Vec<String> = names[..4]
+            .iter()
+            .map(|name| dir.path().join(name).to_str().unwrap().to_string())
+            .collect();
+        let cmd = RenameCommand::Replace {
+            from: r"_v1(\.txt)?$".into(),
+            to: "_v2".into(),
+            use_regex: true,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+
+        let previews = preview_by_risk(paths, cmd, None);
+        let order: Vec<&str> = previews.iter().map(|p| p.name.as_str()).collect();
+        // Conflict, then the rename that drops the extension, then the mild one
+        assert_eq!(order, ["a_v1.txt", "b_v1.txt", "c_v1", "d.txt"]);
+        assert!(previews[1].risk_score > previews[2].risk_score);
+        assert_eq!(previews[3].risk_score, 0.0);
+    }
+
+    #[test]
+    fn test_rename_script() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["alpha.txt", "beta.txt"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                File::create(&path).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let cmd = RenameCommand::Script {
+            source: r#"let s = stem; s.make_upper(); `${index + 1}_${s}.${ext}`"#.into(),
+        };
+
+        let results = handle_rename_batch(paths, cmd, None);
+        let names: Vec<_> = results.iter().map(|r| r.new_name.as_deref()).collect();
+        assert_eq!(names, [Some("1_ALPHA.txt"), Some("2_BETA.txt")]);
+    }
+
+    #[test]
+    fn test_rename_script_failures() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("a.txt");
+        File::create(&file_path).unwrap();
+
+        for (source, status) in [
+            ("loop {}", "Script error"),
+            ("42", "Script returned i64 instead of a string"),
+            (r#"import "os" as os; name"#, "Script error"),
+            (r#""sub/" + name"#, "Illegal characters"),
+        ] {
+            let cmd = RenameCommand::Script {
+                source: source.into(),
+            };
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert!(res.status.starts_with(status), "{}: {}", source, res.status);
+            assert!(file_path.exists());
+        }
+    }
+
+    #[test]
+    fn test_export_plan_unified_diff() {
+        let dir = tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        let files = [
+            sub.join("b_v1.txt"),
+            dir.path().join("c_v1.txt"),
+            sub.join("a_v1.txt"),
+            dir.path().join("notes.txt"),
+        ];
+        for file in &files {
+            File::create(file).unwrap();
+        }
+        let paths: Vec<String> = files
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
+        let cmd = RenameCommand::Replace {
+            from: "v1".into(),
+            to: "v2".into(),
+            use_regex: false,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            preserve_ext: false,
+            literal_replacement: false,
+            preserve_case: false,
+        };
+
+        let diff = export_plan(paths, cmd, None, PlanFormat::UnifiedDiff);
+        let (root, sub) = (dir.path().to_str().unwrap(), sub.to_str().unwrap());
+        assert_eq!(
+            diff,
+            format!(
+                "--- {root}\n+++ {root}\n- c_v1.txt\n+ c_v2.txt\n\
+                 --- {sub}\n+++ {sub}\n- a_v1.txt\n+ a_v2.txt\n- b_v1.txt\n+ b_v2.txt\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_rename_batch_numeric_dedup() {
+        let dir = tempdir().unwrap();
+        let pairs: Vec<(String, String)> = [
+            ("a.txt", "photo.txt"),
+            ("b.txt", "photo.txt"),
+            ("c.txt", "photo (2).txt"),
+            ("d.txt", "photo.txt"),
+        ]
+        .iter()
+        .map(|(old, new)| {
+            let path = dir.path().join(old);
+            File::create(&path).unwrap();
+            (path.to_str().unwrap().to_string(), new.to_string())
+        })
+        .collect();
+        let options = RenameOptions {
+            dedup_strategy: DedupStrategy::Numeric,
+            ..Default::default()
+        };
+
+        let results = apply_names(pairs, Some(options));
+        let names: Vec<_> = results.iter().map(|r| r.new_name.as_deref()).collect();
+        // ` (2)` is already asked for by `c.txt`, so numbering skips it
+        assert_eq!(
+            names,
+            [
+                Some("photo.txt"),
+                Some("photo (3).txt"),
+                Some("photo (2).txt"),
+                Some("photo (4).txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rename_batch_path_component_dedup() {
+        let dir = tempdir().unwrap();
+        let pairs: Vec<(String, String)> = [
+            ("beach.jpg", "photo.jpg"),
+            ("party.jpg", "photo.jpg"),
+            ("party.png", "scan.png"),
+            ("party.gif", "scan.png"),
+            ("solo.txt", "solo.md"),
+        ]
+        .iter()
+        .map(|(old, new)| {
+            let path = dir.path().join(old);
+            File::create(&path).unwrap();
+            (path.to_str().unwrap().to_string(), new.to_string())
+        })
+        .collect();
+        let options = RenameOptions {
+            dedup_strategy: DedupStrategy::PathComponent,
+            ..Default::default()
+        };
+
+        let results = apply_names(pairs, Some(options));
+        let names: Vec<_> = results.iter().map(|r| r.new_name.as_deref()).collect();
+        // Equal stems fall through to the full old name
+        assert_eq!(
+            names,
+            [
+                Some("photo (beach).jpg"),
+                Some("photo (party).jpg"),
+                Some("scan (party.png).png"),
+                Some("scan (party.gif).png"),
+                Some("solo.md"),
+            ]
+        );
+        assert!(results.iter().all(|r| r.code == ResultCode::Success));
+    }
+
+    #[test]
+    fn test_path_tags_reach_folders() {
+        let path = Path::new("/photos/2023/trip/img.jpg");
+        let tags: Vec<_> = path_tags(path)
+            .into_iter()
+            .map(|t| t.to_str().unwrap())
+            .collect();
+        assert_eq!(tags, ["img", "img.jpg", "trip", "2023", "photos"]);
+    }
+
+    #[test]
+    fn test_rename_case_only_if() {
+        for (only_if, name, expected) in [
+            (CaseCondition::AllLower, "img001.jpg", ResultCode::Success),
+            (CaseCondition::AllLower, "iPhone.jpg", ResultCode::Unchanged),
+            (CaseCondition::AllUpper, "README.md", ResultCode::Success),
+            (CaseCondition::AllUpper, "ReadMe.md", ResultCode::Unchanged),
+        ] {
+            let dir = tempdir().unwrap();
+            let file_path = dir.path().join(name);
+            File::create(&file_path).unwrap();
+            let mode = match only_if {
+                CaseCondition::AllUpper => CaseMode::Lower,
+                _ => CaseMode::Upper,
+            };
+            let cmd = RenameCommand::Case {
+                mode,
+                split: ExtSplit::LastDot,
+                only_if,
+                include_ext: false,
+            };
+
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(res.code, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_case_condition_json() {
+        let json = r#"{"mode":"Case","config":{"mode":"upper","only_if":"all_lower"}}"#;
+        let cmd: RenameCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            cmd,
+            RenameCommand::Case {
+                only_if: CaseCondition::AllLower,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_library_api() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("notes.txt");
+        File::create(&file_path).unwrap();
+        let cmd = RenameCommand::Add {
+            text: "old_".into(),
+            position: Position::Start,
+            repad_trailing: None,
+        };
+
+        assert_eq!(compute_name(&file_path, &cmd).unwrap(), "old_notes.txt");
+        assert!(file_path.exists());
+
+        let res = execute(file_path.to_str().unwrap(), &cmd, &RenameOptions::default());
+        assert_eq!(res.code(), ResultCode::Success);
+        assert_eq!(res.new_name(), Some("old_notes.txt"));
+
+        let err = compute_name(&file_path, &cmd).unwrap_err();
+        assert_eq!(err.code(), ResultCode::NotFound);
+    }
+
+    #[test]
+    fn test_retry_failed() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                File::create(&path).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let blocker = dir.path().join("f2.txt");
+        File::create(&blocker).unwrap();
+        let cmd = || RenameCommand::Serial {
+            prefix: "f".into(),
+            suffix: "".into(),
+            number: 1,
+            pad: 1,
+            keep_ext: true,
+            keep_original: false,
+            pad_side: PadSide::Left,
+            pad_char: '0',
+            sign: SignMode::Negative,
+            radix: Radix::Decimal,
+        };
+
+        let results = handle_rename_batch(paths, cmd(), None);
+        assert_eq!(results[1].code, ResultCode::TargetExists);
+
+        fs::remove_file(&blocker).unwrap();
+        let results = retry_failed(results, cmd(), None);
+        let names: Vec<_> = results.iter().map(|r| r.new_name.as_deref()).collect();
+        // Only `b.txt` ran again, still as the second file of the batch
+        assert_eq!(names, [Some("f1.txt"), Some("f2.txt"), Some("f3.txt")]);
+        assert!(results.iter().all(|r| r.code == ResultCode::Success));
+    }
+
+    #[test]
+    fn test_retry_failed_time_order_serial() {
+        let dir = tempdir().unwrap();
+        let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let paths: Vec<String> = [("a.txt", 0), ("b.txt", 60), ("c.txt", 120)]
+            .iter()
+            .map(|(name, offset)| {
+                let p = dir.path().join(name);
+                File::create(&p)
+                    .unwrap()
+                    .set_modified(base + Duration::from_secs(*offset))
+                    .unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        let blocker = dir.path().join("p2.txt");
+        File::create(&blocker).unwrap();
+        let cmd = || RenameCommand::TimeOrderSerial {
+            prefix: "p".into(),
+            pad: 1,
+            keep_ext: true,
+        };
+
+        let results = handle_rename_batch(paths, cmd(), None);
+        assert_eq!(results[1].code, ResultCode::TargetExists);
+
+        fs::remove_file(&blocker).unwrap();
+        let results = retry_failed(results, cmd(), None);
+        let names: Vec<_> = results.iter().map(|r| r.new_name.as_deref()).collect();
+        assert_eq!(names, [Some("p1.txt"), Some("p2.txt"), Some("p3.txt")]);
+        assert!(results.iter().all(|r| r.code == ResultCode::Success));
+    }
+
+    #[test]
+    fn test_transactional_batch() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+        File::create(dir.path().join("x_b.txt")).unwrap();
+        let cmd = || RenameCommand::Add {
+            text: "x_".into(),
+            position: Position::Start,
+            repad_trailing: None,
+        };
+        let paths = || vec![a.to_str().unwrap().into(), b.to_str().unwrap().into()];
+        let options = || RenameOptions {
+            transactional: true,
+            ..Default::default()
+        };
+
+        let results = handle_rename_batch(paths(), cmd(), Some(options()));
+        assert_eq!(results[0].code, ResultCode::Aborted);
+        assert_eq!(results[0].new_name.as_deref(), Some("x_a.txt"));
+        assert_eq!(results[1].code, ResultCode::TargetExists);
+        assert!(a.exists() && b.exists());
+
+        fs::remove_file(dir.path().join("x_b.txt")).unwrap();
+        let results = handle_rename_batch(paths(), cmd(), Some(options()));
+        assert!(results.iter().all(|r| r.code == ResultCode::Success));
+        assert!(dir.path().join("x_a.txt").exists());
+    }
+
+    #[test]
+    fn test_transactional_batch_in_run_order() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<String> = ["img1.png", "img2.png"]
+            .iter()
+            .map(|name| {
+                let p = dir.path().join(name);
+                File::create(&p).unwrap();
+                p.to_str().unwrap().to_string()
+            })
+            .collect();
+        // img1 -> img2, img2 -> img3: fine backwards, blocked forwards
+        let bump = |reverse_order| {
+            let options = RenameOptions {
+                transactional: true,
+                reverse_order,
+                ..Default::default()
+            };
+            let ops = paths
+                .iter()
+                .zip(["img2.png", "img3.png"])
+                .map(|(path, name)| (path.clone(), name.to_string()))
+                .collect();
+            apply_names(ops, Some(options))
+        };
+        let results = bump(false);
+        assert_eq!(results[0].code, ResultCode::TargetExists);
+        assert_eq!(results[1].code, ResultCode::Aborted);
+        assert!(!dir.path().join("img3.png").exists());
+
+        let results = bump(true);
+        assert!(results.iter().all(|r| r.code == ResultCode::Success));
+        assert!(!dir.path().join("img1.png").exists());
+        assert!(dir.path().join("img3.png").exists());
+    }
+
+    #[test]
+    fn test_extended_length_path() {
+        let deep: String = (0..30).map(|i| format!("folder{:02}/", i)).collect();
+        let long = format!("C:/{}photo.jpg", deep);
+        let extended = extended_length_path(&long).unwrap();
+        assert!(extended.starts_with(r"\\?\C:\folder00\folder01\"));
+        assert!(extended.ends_with(r"\photo.jpg"));
+        assert_eq!(extended_length_path(&extended), None);
+
+        let unc = format!(r"\\server\share\{}", deep.replace('/', r"\"));
+        assert!(extended_length_path(&unc)
+            .unwrap()
+            .starts_with(r"\\?\UNC\server\share\"));
+
+        // Short or relative paths work without the prefix
+        assert_eq!(extended_length_path(r"C:\photos\a.jpg"), None);
+        assert_eq!(extended_length_path(&deep), None);
+    }
+
+    #[test]
+    fn test_rename_match_sibling() {
+        let dir = tempdir().unwrap();
+        for name in [
+            "IMG_001.raw",
+            "IMG_001 beach.JPG",
+            "IMG_0015.jpg",
+            "IMG_002.raw",
+        ] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let cmd = || RenameCommand::MatchSibling {
+            reference_ext: "jpg".into(),
+        };
+
+        let res = handle_rename(
+            dir.path().join("IMG_001.raw").to_str().unwrap().into(),
+            cmd(),
+            None,
+        );
+        assert_eq!(res.new_name.as_deref(), Some("IMG_001 beach.raw"));
+
+        let res = handle_rename(
+            dir.path().join("IMG_002.raw").to_str().unwrap().into(),
+            cmd(),
+            None,
+        );
+        assert_eq!(res.code, ResultCode::NoReference);
+        assert_eq!(res.status, "No reference found");
+    }
+
+    #[test]
+    fn test_rename_case_include_ext() {
+        for (include_ext, expected) in [(false, "photo.JPG"), (true, "photo.jpg")] {
+            let dir = tempdir().unwrap();
+            let file_path = dir.path().join("Photo.JPG");
+            File::create(&file_path).unwrap();
+            let cmd = RenameCommand::Case {
+                mode: CaseMode::Lower,
+                split: ExtSplit::LastDot,
+                only_if: CaseCondition::Always,
+                include_ext,
+            };
+
+            let res = handle_rename(file_path.to_str().unwrap().into(), cmd, None);
+            assert_eq!(res.new_name.as_deref(), Some(expected));
+        }
+    }
+}
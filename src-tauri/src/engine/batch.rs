@@ -0,0 +1,991 @@
+// The library API: single renames, batches, retries, previews and reports.
+
+use super::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+// --- Library API ---
+
+/// Rename the file at `path` with `cmd`, running every check the app does.
+/// The entry point for using the engine without the desktop app.
+pub fn execute(path: &str, cmd: &RenameCommand, options: &RenameOptions) -> RenameResult {
+    let prepared = match PreparedOptions::new(options) {
+        Ok(p) => p,
+        Err(e) => return RenameResult::from_error(path.to_string(), e),
+    };
+    rename_path(
+        path.to_string(),
+        Path::new(path),
+        &PreparedCommand::new(cmd),
+        0,
+        &prepared,
+    )
+}
+
+/// The name `cmd` would give the existing file at `path`, without renaming
+/// it or checking the result. `SortInto` names come with their subfolder.
+pub fn compute_name(path: &Path, cmd: &RenameCommand) -> Result<String, NameError> {
+    let options = RenameOptions::default();
+    let prepared = PreparedOptions::new(&options)?;
+    plan_rename(path, &PreparedCommand::new(cmd), 0, &prepared).map(|plan| plan.display_name())
+}
+
+/// Same as `handle_rename`, but takes the path as raw OS bytes so names that
+/// aren't valid UTF-8 (and can't travel in a JSON string) can still be renamed.
+/// Such names are echoed back in `path_bytes` / `new_name_bytes`.
+pub fn handle_rename_bytes(
+    path: Vec<u8>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> RenameResult {
+    let old_path = match path_from_bytes(path) {
+        Some(p) => p,
+        None => {
+            return RenameResult::failure(String::new(), ResultCode::InvalidPath, "Invalid path")
+        }
+    };
+    let display_path = old_path.to_string_lossy().into_owned();
+
+    let options = options.unwrap_or_default();
+    let mut res = match PreparedOptions::new(&options) {
+        Ok(prepared) => rename_path(
+            display_path,
+            &old_path,
+            &PreparedCommand::new(&cmd),
+            0,
+            &prepared,
+        ),
+        Err(e) => RenameResult::from_error(display_path, e),
+    };
+    if old_path.to_str().is_none() {
+        res.path_bytes = Some(old_path.as_os_str().as_encoded_bytes().to_vec());
+    }
+    res
+}
+
+/// One entry of a batch: the path, the command to apply and the index passed
+/// to `compute_new_name`.
+struct BatchOp<'a> {
+    pub(super) path: String,
+    pub(super) cmd: &'a PreparedCommand<'a>,
+    pub(super) index: usize,
+}
+
+/// Flag every plan whose target was already claimed by an earlier one. With
+/// `fold_case`, targets equal up to case also collide when their directory is
+/// on a case-insensitive filesystem, which is only probed when that matters.
+fn find_duplicates(plans: &[Result<PlannedRename, NameError>], fold_case: bool) -> Vec<bool> {
+    let mut claimed = HashSet::new();
+    let mut claimed_folded = HashSet::new();
+    let mut insensitive_dirs: HashMap<PathBuf, bool> = HashMap::new();
+
+    plans
+        .iter()
+        .map(|plan| {
+            let Ok(plan) = plan else {
+                return false;
+            };
+            let Some(target) = plan.target() else {
+                return false;
+            };
+            let folded = plan
+                .target_dir()
+                .join(plan.new_name.to_string_lossy().to_lowercase());
+            let exact_dup = !claimed.insert(target);
+            let folded_dup = !claimed_folded.insert(folded);
+
+            exact_dup
+                || (fold_case
+                    && folded_dup
+                    && *insensitive_dirs
+                        .entry(plan.target_dir())
+                        .or_insert_with_key(|dir| is_case_insensitive(dir)))
+        })
+        .collect()
+}
+
+fn dedup_plans(plans: &mut [Result<PlannedRename, NameError>], strategy: DedupStrategy) {
+    match strategy {
+        DedupStrategy::Fail => {}
+        DedupStrategy::Numeric => number_duplicates(plans),
+        DedupStrategy::PathComponent => tag_duplicates(plans),
+    }
+}
+
+/// Give every plan whose target an earlier plan already claimed the first
+/// ` (n)` variant, from 2 up, that no other plan in the batch targets.
+fn number_duplicates(plans: &mut [Result<PlannedRename, NameError>]) {
+    let template = default_suffix_template();
+    let mut taken: HashSet<PathBuf> = plans
+        .iter()
+        .flatten()
+        .filter_map(PlannedRename::target)
+        .collect();
+    let mut claimed = HashSet::new();
+    for plan in plans.iter_mut().flatten() {
+        let Some(target) = plan.target() else {
+            continue;
+        };
+        if claimed.insert(target) {
+            continue;
+        }
+        let dir = plan.target_dir();
+        let free = (2..=MAX_SUFFIX_ATTEMPTS)
+            .map(|n| suffixed_name(&plan.new_name, &template, n))
+            .find(|name| !taken.contains(&dir.join(name)));
+        if let Some(name) = free {
+            taken.insert(dir.join(&name));
+            claimed.insert(dir.join(&name));
+            plan.new_name = name;
+        }
+    }
+}
+
+/// The parts of `path` [`tag_duplicates`] may tag a name with, nearest first:
+/// stem, file name, then each folder up to the root.
+pub(super) fn path_tags(path: &Path) -> Vec<&OsStr> {
+    let folders = path
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components().rev())
+        .filter_map(|part| match part {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        });
+    path.file_stem()
+        .into_iter()
+        .chain(path.file_name())
+        .chain(folders)
+        .collect()
+}
+
+/// `name` with ` (<tag>)` before its extension.
+fn tagged_name(name: &OsStr, tag: &OsStr) -> OsString {
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or(name);
+    match path.extension() {
+        Some(ext) => concat_os(&[stem, OsStr::new(" ("), tag, OsStr::new(")."), ext]),
+        None => concat_os(&[stem, OsStr::new(" ("), tag, OsStr::new(")")]),
+    }
+}
+
+/// Tag every plan in a group sharing a target with the nearest part of its
+/// source path that no other plan of the group shares. A group is left as
+/// it is when no part tells all of its files apart, or when the tagged
+/// names would collide with another target of the batch.
+fn tag_duplicates(plans: &mut [Result<PlannedRename, NameError>]) {
+    let mut groups: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+    for (i, plan) in plans.iter().enumerate() {
+        if let Some(target) = plan.as_ref().ok().and_then(PlannedRename::target) {
+            groups.entry(target).or_default().push(i);
+        }
+    }
+    let mut taken: HashSet<PathBuf> = groups.keys().cloned().collect();
+
+    for group in groups.into_values().filter(|group| group.len() > 1) {
+        let tags: Vec<Vec<&OsStr>> = group
+            .iter()
+            .filter_map(|&i| plans[i].as_ref().ok())
+            .map(|plan| path_tags(plan.old_path))
+            .collect();
+        let depth = tags.iter().map(Vec::len).min().unwrap_or(0);
+        let level = (0..depth).find(|&level| {
+            let distinct: HashSet<&OsStr> = tags.iter().map(|t| t[level]).collect();
+            distinct.len() == tags.len()
+        });
+        let Some(level) = level else {
+            continue;
+        };
+
+        let renamed: Vec<(usize, OsString, PathBuf)> = group
+            .iter()
+            .zip(&tags)
+            .filter_map(|(&i, tags)| {
+                let plan = plans[i].as_ref().ok()?;
+                let name = tagged_name(&plan.new_name, tags[level]);
+                let target = plan.target_dir().join(&name);
+                Some((i, name, target))
+            })
+            .collect();
+        if renamed.iter().any(|(_, _, target)| taken.contains(target)) {
+            continue;
+        }
+        for (i, name, target) in renamed {
+            taken.insert(target);
+            if let Ok(plan) = &mut plans[i] {
+                plan.new_name = name;
+            }
+        }
+    }
+}
+
+/// For each of `paths`, another index whose file has identical content. Only
+/// files sharing a size are hashed, through [`file_checksum_limited`] with
+/// the given limits; files it can't finish never match.
+pub(super) fn duplicate_content(
+    paths: &[PathBuf],
+    max_bytes: Option<u64>,
+    timeout: Option<Duration>,
+) -> Vec<Option<usize>> {
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, path) in paths.iter().enumerate() {
+        if let Ok(meta) = fs::metadata(path) {
+            if meta.is_file() {
+                by_size.entry(meta.len()).or_default().push(i);
+            }
+        }
+    }
+
+    let mut other = vec![None; paths.len()];
+    for group in by_size.values().filter(|group| group.len() > 1) {
+        let mut by_digest: HashMap<String, Vec<usize>> = HashMap::new();
+        for &i in group {
+            let digest = file_checksum_limited(&paths[i], ChecksumAlgo::Sha1, max_bytes, timeout);
+            if let Ok(digest) = digest {
+                by_digest.entry(digest).or_default().push(i);
+            }
+        }
+        for same in by_digest.values().filter(|same| same.len() > 1) {
+            // Each file names the next one, the last wraps to the first
+            for (k, &i) in same.iter().enumerate() {
+                other[i] = Some(same[(k + 1) % same.len()]);
+            }
+        }
+    }
+    other
+}
+
+/// Check every plan the way [`rename_ops`] would when it reaches it, in the
+/// order it runs them: a target an earlier entry moves away from counts as
+/// free, one an earlier entry takes counts as taken. `Ok` is the path the
+/// entry would be renamed to. Verdicts come back in input order.
+fn simulate_batch(
+    plans: &[Result<PlannedRename, NameError>],
+    duplicate: &[bool],
+    options: &PreparedOptions,
+) -> Vec<Result<PathBuf, NameError>> {
+    let mut order: Vec<usize> = (0..plans.len()).collect();
+    if options.options.reverse_order {
+        order.reverse();
+    }
+    let mut vacated: HashSet<PathBuf> = HashSet::new();
+    let mut taken: HashSet<PathBuf> = HashSet::new();
+    let mut verdicts: Vec<Option<Result<PathBuf, NameError>>> = vec![None; plans.len()];
+    for i in order {
+        let verdict = match &plans[i] {
+            Ok(plan) if duplicate[i] => Err(NameError::new(
+                ResultCode::DuplicateTarget,
+                format!("Duplicate target in batch: {}", plan.display_name()),
+            )),
+            Ok(plan) => check_plan_with(plan, options, |path| {
+                taken.contains(path) || (!vacated.contains(path) && path.exists())
+            }),
+            Err(e) => Err(e.clone()),
+        };
+        if let (Ok(plan), Ok(new_path)) = (&plans[i], &verdict) {
+            taken.remove(plan.old_path);
+            vacated.insert(plan.old_path.to_path_buf());
+            vacated.remove(new_path);
+            taken.insert(new_path.clone());
+        }
+        verdicts[i] = Some(verdict);
+    }
+    verdicts.into_iter().flatten().collect()
+}
+
+/// Rename every entry in order. All new names are computed before anything
+/// is renamed, so when two entries would land on the same target the later
+/// ones fail with `DuplicateTarget` instead of racing for it.
+fn rename_ops(ops: Vec<BatchOp>, options: &RenameOptions) -> Vec<RenameResult> {
+    let prepared = match PreparedOptions::new(options) {
+        Ok(p) => p,
+        Err(e) => {
+            return ops
+                .into_iter()
+                .map(|op| RenameResult::new(op.path, e.code, e.message.clone()))
+                .collect()
+        }
+    };
+
+    let old_paths: Vec<PathBuf> = ops.iter().map(|op| PathBuf::from(&op.path)).collect();
+    let infos: Vec<FileInfo> = old_paths
+        .iter()
+        .map(|p| FileInfo::read(p, &prepared))
+        .collect();
+    let used: Vec<(&PreparedCommand, usize)> = ops.iter().map(|op| (op.cmd, op.index)).collect();
+    let ranges = reserve_counters(&used);
+    let mut plans: Vec<Result<PlannedRename, NameError>> = ops
+        .iter()
+        .zip(&old_paths)
+        .map(|(op, old_path)| plan_rename(old_path, op.cmd, op.index, &prepared))
+        .collect();
+    dedup_plans(&mut plans, options.dedup_strategy);
+
+    let duplicate = find_duplicates(&plans, !options.case_sensitive_conflicts);
+    if options.transactional {
+        let verdicts = simulate_batch(&plans, &duplicate, &prepared);
+        let blocked = verdicts
+            .iter()
+            .any(|verdict| matches!(verdict, Err(e) if !is_settled(e.code)));
+        if blocked {
+            let mut results: Vec<RenameResult> = ops
+                .into_iter()
+                .zip(plans)
+                .zip(verdicts)
+                .map(|((op, plan), verdict)| match (plan, verdict) {
+                    (Ok(plan), Ok(_)) => RenameResult {
+                        new_name: Some(plan.display_name()),
+                        ..RenameResult::new(op.path, ResultCode::Aborted, "Batch aborted")
+                    },
+                    (_, Err(e)) | (Err(e), _) => RenameResult::from_error(op.path, e),
+                })
+                .collect();
+            commit_counters(&used, &mut results, ranges);
+            return results;
+        }
+    }
+    // Hashed before renaming, while every old path is still valid
+    let same_content = if options.flag_duplicate_content {
+        duplicate_content(
+            &old_paths,
+            default_hash_max_bytes(),
+            default_hash_timeout_ms().map(Duration::from_millis),
+        )
+    } else {
+        Vec::new()
+    };
+
+    let mut entries: Vec<_> = ops
+        .into_iter()
+        .zip(plans)
+        .zip(duplicate.into_iter().zip(infos))
+        .collect();
+    if options.reverse_order {
+        entries.reverse();
+    }
+    let mut results: Vec<RenameResult> = entries
+        .into_iter()
+        .map(|((op, plan), (duplicate, info))| {
+            let mut res = match plan {
+                Ok(plan) if duplicate => RenameResult::failure(
+                    op.path,
+                    ResultCode::DuplicateTarget,
+                    format!("Duplicate target in batch: {}", plan.display_name()),
+                ),
+                Ok(plan) => execute_rename(op.path, plan, &prepared),
+                Err(e) => RenameResult::from_error(op.path, e),
+            };
+            info.apply_to(&mut res);
+            res
+        })
+        .collect();
+    if options.reverse_order {
+        results.reverse();
+    }
+    for (i, other) in same_content.into_iter().enumerate() {
+        if let Some(other) = other {
+            let note = format!("Duplicate content of {}", results[other].path);
+            results[i].add_warning(note);
+        }
+    }
+    commit_counters(&used, &mut results, ranges);
+    results
+}
+
+/// Rename every path in order with the same options. `Serial` numbers count
+/// up from `number` by position in `paths`, i.e. in drop order.
+pub(crate) fn rename_all(
+    paths: Vec<String>,
+    cmd: &RenameCommand,
+    options: &RenameOptions,
+) -> Vec<RenameResult> {
+    if let Err(e) = check_serial_batch(cmd, paths.len()) {
+        return paths
+            .into_iter()
+            .map(|path| RenameResult::from_error(path, e.clone()))
+            .collect();
+    }
+    let cmd = PreparedCommand::for_batch(cmd, &paths);
+    let ops = paths
+        .into_iter()
+        .enumerate()
+        .map(|(index, path)| BatchOp {
+            path,
+            cmd: &cmd,
+            index,
+        })
+        .collect();
+    rename_ops(ops, options)
+}
+
+pub fn handle_rename_batch(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    rename_all(paths, &cmd, &options.unwrap_or_default())
+}
+
+/// Whether a retry would be pointless: the file was renamed, needed no
+/// change, or was skipped on purpose.
+fn is_settled(code: ResultCode) -> bool {
+    matches!(
+        code,
+        ResultCode::Success
+            | ResultCode::Unchanged
+            | ResultCode::NoMatch
+            | ResultCode::FilteredOut
+            | ResultCode::PreconditionFailed
+    )
+}
+
+/// Run `cmd` again on the entries of an earlier batch's `results` that
+/// failed (locked, permission, taken target, ...). Each keeps its position in
+/// the batch, so `Serial` and friends number it as the first run would have;
+/// the other entries come back as they were.
+pub fn retry_failed(
+    mut results: Vec<RenameResult>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    let paths: Vec<String> = results.iter().map(|r| r.path.clone()).collect();
+    // Renamed files are ranked (by time) where they are now
+    let current: Vec<String> = results
+        .iter()
+        .map(|r| match (r.code, &r.new_name) {
+            (ResultCode::Success, Some(new_name)) => Path::new(&r.path)
+                .with_file_name(new_name)
+                .to_string_lossy()
+                .into_owned(),
+            _ => r.path.clone(),
+        })
+        .collect();
+    let cmd = PreparedCommand::for_batch_at(&cmd, &paths, &current);
+    let failed: Vec<usize> = (0..results.len())
+        .filter(|&i| !is_settled(results[i].code))
+        .collect();
+    let ops = failed
+        .iter()
+        .map(|&index| BatchOp {
+            path: paths[index].clone(),
+            cmd: &cmd,
+            index,
+        })
+        .collect();
+
+    let retried = rename_ops(ops, &options.unwrap_or_default());
+    for (index, res) in failed.into_iter().zip(retried) {
+        results[index] = res;
+    }
+    results
+}
+
+/// Collect every non-directory entry below `dir`, descending at most
+/// `max_depth` levels (`Some(0)`: `dir`'s own files only). Directory symlinks
+/// are not followed, so a link cycle can't trap the walk.
+pub(super) fn collect_files(
+    dir: &Path,
+    max_depth: Option<usize>,
+    out: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            match max_depth {
+                Some(0) => {}
+                depth => collect_files(&entry.path(), depth.map(|d| d - 1), out)?,
+            }
+        } else {
+            out.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Apply `cmd` to every file below `dir` in sorted path order. Directories
+/// themselves are walked but never renamed. With `max_depth`, files more than
+/// that many folders down aren't visited at all (0 = `dir`'s own files).
+pub fn handle_rename_recursive(
+    dir: String,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+    max_depth: Option<usize>,
+) -> Vec<RenameResult> {
+    let mut files = Vec::new();
+    if let Err(e) = collect_files(Path::new(&dir), max_depth, &mut files) {
+        return vec![RenameResult::failure(
+            dir,
+            ResultCode::IoError,
+            e.to_string(),
+        )];
+    }
+    files.retain(|p| !is_sidecar(p));
+    files.sort();
+
+    let paths = files
+        .into_iter()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    rename_all(paths, &cmd, &options.unwrap_or_default())
+}
+
+/// Rename a batch where each path carries its own command. Commands are
+/// applied as given, so `Serial` numbers are not offset by position.
+/// `Counter` commands sharing a key count up in order, one number per file.
+pub fn handle_rename_mixed(
+    ops: Vec<(String, RenameCommand)>,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    let cmds: Vec<PreparedCommand> = ops
+        .iter()
+        .map(|(_, cmd)| PreparedCommand::new(cmd))
+        .collect();
+    let mut counter_seen: HashMap<&str, usize> = HashMap::new();
+    let ops = ops
+        .iter()
+        .zip(&cmds)
+        .map(|((path, _), cmd)| {
+            let index = match counter_key(cmd) {
+                Some(key) => {
+                    let seen = counter_seen.entry(key).or_default();
+                    *seen += 1;
+                    *seen - 1
+                }
+                None => 0,
+            };
+            BatchOp {
+                path: path.clone(),
+                cmd,
+                index,
+            }
+        })
+        .collect();
+    rename_ops(ops, &options.unwrap_or_default())
+}
+
+/// Rename each `(path, new_name)` pair to exactly `new_name`, extension
+/// included. The names go through the same validation, safe mode and
+/// duplicate-target checks as any batch, so a UI that collects one name per
+/// file doesn't have to repeat them.
+pub fn apply_names(
+    pairs: Vec<(String, String)>,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    let ops = pairs
+        .into_iter()
+        .map(|(path, name)| {
+            (
+                path,
+                RenameCommand::Fixed {
+                    name,
+                    keep_ext: false,
+                    expand_tokens: false,
+                },
+            )
+        })
+        .collect();
+    handle_rename_mixed(ops, options)
+}
+
+/// Rename the sorted `paths` to the lines of `names_file`, one name per line
+/// in order. With `keep_ext` each file keeps its extension and the lines are
+/// stems. If the counts differ only the matched prefix is renamed; the rest
+/// of the files (or the surplus names, reported against `names_file`) get
+/// `NameListMismatch`. Blank lines at the end of the list are ignored.
+pub fn apply_name_list(
+    mut paths: Vec<String>,
+    names_file: String,
+    keep_ext: bool,
+    options: Option<RenameOptions>,
+) -> Vec<RenameResult> {
+    let contents = match fs::read_to_string(&names_file) {
+        Ok(c) => c,
+        Err(e) => {
+            return vec![RenameResult::failure(
+                names_file,
+                ResultCode::IoError,
+                e.to_string(),
+            )]
+        }
+    };
+    let mut names: Vec<&str> = contents.lines().collect();
+    // Blank lines at the end are editor leftovers, not names
+    while names.last().is_some_and(|name| name.trim().is_empty()) {
+        names.pop();
+    }
+    paths.sort();
+
+    let mismatch = format!(
+        "Name list has {} names for {} files",
+        names.len(),
+        paths.len()
+    );
+    let matched = paths.len().min(names.len());
+    let unmatched = paths.split_off(matched);
+
+    let cmds: Vec<RenameCommand> = names[..matched]
+        .iter()
+        .map(|name| RenameCommand::Fixed {
+            name: name.to_string(),
+            keep_ext,
+            expand_tokens: false,
+        })
+        .collect();
+    let prepared: Vec<PreparedCommand> = cmds.iter().map(PreparedCommand::new).collect();
+    let ops = paths
+        .into_iter()
+        .zip(&prepared)
+        .map(|(path, cmd)| BatchOp {
+            path,
+            cmd,
+            index: 0,
+        })
+        .collect();
+
+    let mut results = rename_ops(ops, &options.unwrap_or_default());
+    results.extend(
+        unmatched
+            .into_iter()
+            .map(|path| RenameResult::failure(path, ResultCode::NameListMismatch, &mismatch)),
+    );
+    if names.len() > matched {
+        results.push(RenameResult::failure(
+            names_file,
+            ResultCode::NameListMismatch,
+            mismatch,
+        ));
+    }
+    results
+}
+
+/// Pre-flight verdict for one path of [`analyze_batch`]. `code` is `Success`
+/// when the file would be renamed; otherwise it's what the real run reports.
+#[derive(Serialize, Deserialize)]
+pub struct SkipReason {
+    pub(super) path: String,
+    pub(super) code: ResultCode,
+    pub(super) status: String,
+}
+
+/// Plan every path the way `handle_rename_batch` would and run all of its
+/// checks without renaming. Each entry is the computed new name, if any, and
+/// `Ok` when the rename would go ahead.
+fn dry_run(
+    paths: &[String],
+    cmd: &RenameCommand,
+    options: &RenameOptions,
+) -> Vec<(Option<String>, Result<(), NameError>)> {
+    let prepared =
+        PreparedOptions::new(options).and_then(|p| check_serial_batch(cmd, paths.len()).map(|_| p));
+    let prepared = match prepared {
+        Ok(p) => p,
+        Err(e) => return paths.iter().map(|_| (None, Err(e.clone()))).collect(),
+    };
+    let cmd = PreparedCommand::for_batch(cmd, paths);
+
+    let old_paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    let mut plans: Vec<Result<PlannedRename, NameError>> = old_paths
+        .iter()
+        .enumerate()
+        .map(|(index, old_path)| plan_rename(old_path, &cmd, index, &prepared))
+        .collect();
+    dedup_plans(&mut plans, options.dedup_strategy);
+    let duplicate = find_duplicates(&plans, !options.case_sensitive_conflicts);
+    let verdicts = simulate_batch(&plans, &duplicate, &prepared);
+
+    plans
+        .into_iter()
+        .zip(verdicts)
+        .map(|(plan, verdict)| {
+            let new_name = plan.ok().map(|plan| plan.display_name());
+            (new_name, verdict.map(|_| ()))
+        })
+        .collect()
+}
+
+/// Run every check `handle_rename_batch` would (filter, missing file, name
+/// validation, safe mode, occupied and duplicate targets) without renaming.
+pub fn analyze_batch(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> Vec<SkipReason> {
+    let verdicts = dry_run(&paths, &cmd, &options.unwrap_or_default());
+    paths
+        .into_iter()
+        .zip(verdicts)
+        .map(|(path, (_, verdict))| match verdict {
+            Ok(()) => SkipReason {
+                path,
+                code: ResultCode::Success,
+                status: "Would rename".to_string(),
+            },
+            Err(e) => SkipReason {
+                path,
+                code: e.code,
+                status: e.message,
+            },
+        })
+        .collect()
+}
+
+/// One file of [`list_with_preview`]: its current and proposed name plus the
+/// verdict [`analyze_batch`] would give. `new_name` is `None` when the name
+/// can't be computed.
+#[derive(Serialize, Deserialize)]
+pub struct FilePreview {
+    pub(super) path: String,
+    pub(super) name: String,
+    pub(super) new_name: Option<String>,
+    pub(super) code: ResultCode,
+    pub(super) status: String,
+    /// [`risk_score`] of the rename; 0.0 when it won't happen.
+    pub(super) risk_score: f64,
+}
+
+impl FilePreview {
+    fn is_conflict(&self) -> bool {
+        matches!(
+            self.code,
+            ResultCode::TargetExists | ResultCode::DuplicateTarget
+        )
+    }
+}
+
+/// List the files in `dir` (below it with `recursive`) in sorted path order
+/// with their previews, from one directory snapshot. Directories are never
+/// listed, as with `handle_rename_recursive`.
+pub fn list_with_preview(dir: String, cmd: RenameCommand, recursive: bool) -> Vec<FilePreview> {
+    let mut files = Vec::new();
+    let max_depth = if recursive { None } else { Some(0) };
+    if let Err(e) = collect_files(Path::new(&dir), max_depth, &mut files) {
+        return vec![FilePreview {
+            name: String::new(),
+            path: dir,
+            new_name: None,
+            code: ResultCode::IoError,
+            status: e.to_string(),
+            risk_score: 0.0,
+        }];
+    }
+    files.retain(|p| !is_sidecar(p));
+    files.sort();
+
+    let paths: Vec<String> = files
+        .iter()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    file_previews(paths, &cmd, &RenameOptions::default())
+}
+
+/// [`dry_run`] verdicts for `paths` as [`FilePreview`]s, in input order.
+fn file_previews(
+    paths: Vec<String>,
+    cmd: &RenameCommand,
+    options: &RenameOptions,
+) -> Vec<FilePreview> {
+    let verdicts = dry_run(&paths, cmd, options);
+    paths
+        .into_iter()
+        .zip(verdicts)
+        .map(|(path, (new_name, verdict))| {
+            let (code, status) = match verdict {
+                Ok(()) => (ResultCode::Success, "Would rename".to_string()),
+                Err(e) => (e.code, e.message),
+            };
+            let name = Path::new(&path)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let risk_score = match &new_name {
+                Some(new_name) if code == ResultCode::Success => risk_score(&name, new_name),
+                _ => 0.0,
+            };
+            FilePreview {
+                name,
+                path,
+                new_name,
+                code,
+                status,
+                risk_score,
+            }
+        })
+        .collect()
+}
+
+/// Preview a batch riskiest first, for reviewing big jobs: conflicts, then
+/// renames by descending `risk_score`, then files that won't change. Ties
+/// keep input order.
+pub fn preview_by_risk(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> Vec<FilePreview> {
+    let mut previews = file_previews(paths, &cmd, &options.unwrap_or_default());
+    previews.sort_by(|a, b| {
+        b.is_conflict()
+            .cmp(&a.is_conflict())
+            .then(b.risk_score.total_cmp(&a.risk_score))
+    });
+    previews
+}
+
+/// Layout of [`export_plan`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanFormat {
+    /// The [`FilePreview`] of every path, as a JSON array in input order.
+    Json,
+    /// The renames that would go ahead as `- old` / `+ new` lines under a
+    /// `---` / `+++` header per directory, like a unified diff.
+    UnifiedDiff,
+}
+
+/// The plan for a batch as a text artifact to attach to a review. Output
+/// depends only on the inputs and the files on disk: directories and names
+/// are sorted in the diff layout.
+pub fn export_plan(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+    format: PlanFormat,
+) -> String {
+    let previews = file_previews(paths, &cmd, &options.unwrap_or_default());
+    match format {
+        PlanFormat::Json => serde_json::to_string_pretty(&previews).unwrap_or_default(),
+        PlanFormat::UnifiedDiff => unified_diff(&previews),
+    }
+}
+
+fn unified_diff(previews: &[FilePreview]) -> String {
+    let mut by_dir: BTreeMap<String, Vec<(&str, &str)>> = BTreeMap::new();
+    for preview in previews.iter().filter(|p| p.code == ResultCode::Success) {
+        let (Some(new_name), Some(dir)) = (&preview.new_name, Path::new(&preview.path).parent())
+        else {
+            continue;
+        };
+        by_dir
+            .entry(dir.to_string_lossy().into_owned())
+            .or_default()
+            .push((&preview.name, new_name));
+    }
+
+    let mut out = String::new();
+    for (dir, mut renames) in by_dir {
+        renames.sort();
+        out.push_str(&format!("--- {}\n+++ {}\n", dir, dir));
+        for (old, new) in renames {
+            out.push_str(&format!("- {}\n+ {}\n", old, new));
+        }
+    }
+    out
+}
+
+/// [`FilePreview`]s bucketed by outcome, each bucket in input order.
+#[derive(Serialize, Deserialize, Default)]
+pub struct GroupedPreview {
+    pub(super) will_rename: Vec<FilePreview>,
+    /// `Unchanged` and `NoMatch`.
+    pub(super) no_op: Vec<FilePreview>,
+    /// Targets already taken on disk or by an earlier file in the batch.
+    pub(super) conflicts: Vec<FilePreview>,
+    /// Everything else: filtered, missing, invalid names, safe mode, ...
+    pub(super) errors: Vec<FilePreview>,
+}
+
+/// Preview a batch like [`list_with_preview`], already grouped for a
+/// tabbed summary.
+pub fn preview_grouped(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> GroupedPreview {
+    let mut grouped = GroupedPreview::default();
+    for preview in file_previews(paths, &cmd, &options.unwrap_or_default()) {
+        let bucket = match preview.code {
+            ResultCode::Success => &mut grouped.will_rename,
+            ResultCode::Unchanged | ResultCode::NoMatch => &mut grouped.no_op,
+            _ if preview.is_conflict() => &mut grouped.conflicts,
+            _ => &mut grouped.errors,
+        };
+        bucket.push(preview);
+    }
+    grouped
+}
+
+/// Headline numbers from [`count_changes`].
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct ChangeCount {
+    pub(super) will_change: usize,
+    /// Files the command leaves as they are (`Unchanged`, `NoMatch`).
+    pub(super) no_op: usize,
+    /// Targets already taken on disk or by an earlier file in the batch.
+    pub(super) conflict: usize,
+    /// Everything else: filtered, missing, invalid names, safe mode, ...
+    pub(super) skipped: usize,
+}
+
+/// Tally what `handle_rename_batch` would do, using the same checks and
+/// groups as [`preview_grouped`], for a confirmation dialog.
+pub fn count_changes(
+    paths: Vec<String>,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> ChangeCount {
+    let grouped = preview_grouped(paths, cmd, options);
+    ChangeCount {
+        will_change: grouped.will_rename.len(),
+        no_op: grouped.no_op.len(),
+        conflict: grouped.conflicts.len(),
+        skipped: grouped.errors.len(),
+    }
+}
+
+/// Old path → new name for each of `paths` under `cmd`, without touching the
+/// disk. Cheap enough to call on every keystroke: the command's regex is
+/// compiled once and no validation or conflict checks run. Files whose name
+/// can't be computed map to an empty string.
+pub fn preview_many(paths: Vec<String>, cmd: RenameCommand) -> Vec<(String, String)> {
+    let options = RenameOptions::default();
+    let options = PreparedOptions {
+        options: &options,
+        name_filter: None,
+        batch_id: String::new(),
+        ops_written: Cell::new(0),
+    };
+    let cmd = PreparedCommand::for_batch(&cmd, &paths);
+
+    paths
+        .into_iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let new_name = match plan_rename(Path::new(&path), &cmd, index, &options) {
+                Ok(plan) => plan.display_name(),
+                Err(_) => String::new(),
+            };
+            (path, new_name)
+        })
+        .collect()
+}
+
+/// Non-overlapping matches of `pattern` in each file's whole name, for tuning
+/// a regex before a `Replace`. An invalid pattern fails the whole call.
+pub fn count_matches(paths: Vec<String>, pattern: String) -> Result<Vec<(String, usize)>, String> {
+    let re = Regex::new(&pattern).map_err(|e| format!("Regex error: {}", e))?;
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let count = Path::new(&path)
+                .file_name()
+                .map_or(0, |name| re.find_iter(&name.to_string_lossy()).count());
+            (path, count)
+        })
+        .collect())
+}
@@ -0,0 +1,181 @@
+// Persistent `Counter` numbers: the store, and reserving and handing back a
+// batch's numbers.
+
+use super::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+// --- Persistent counters ---
+
+/// Directory holding the `Counter` store; see [`set_counter_dir`].
+pub(super) static COUNTER_DIR: OnceLock<PathBuf> = OnceLock::new();
+/// Serializes read-modify-write cycles on the counter file within this
+/// process; [`lock_counters`] adds a file lock for other processes.
+static COUNTER_LOCK: Mutex<()> = Mutex::new(());
+
+const COUNTER_FILE: &str = "counters.json";
+
+/// Keep the `Counter` store in `dir`, normally the app data dir. Only the
+/// first call takes effect; until then `Counter` reports its store as
+/// unavailable.
+pub fn set_counter_dir(dir: PathBuf) {
+    let _ = COUNTER_DIR.set(dir);
+}
+
+fn counter_io_error(e: io::Error) -> NameError {
+    NameError::new(ResultCode::IoError, format!("Counter store error: {}", e))
+}
+
+fn counter_file() -> Result<PathBuf, NameError> {
+    COUNTER_DIR
+        .get()
+        .map(|dir| dir.join(COUNTER_FILE))
+        .ok_or_else(|| NameError::new(ResultCode::IoError, "Counter store unavailable"))
+}
+
+/// Next number per counter key.
+fn load_counters(path: &Path) -> io::Result<HashMap<String, u64>> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write through a temp file and rename over the old one, so a crash can't
+/// leave a half-written store behind.
+fn save_counters(path: &Path, counters: &HashMap<String, u64>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(
+        &tmp,
+        serde_json::to_string(counters).map_err(io::Error::other)?,
+    )?;
+    fs::rename(&tmp, path)
+}
+
+/// Hold the counter store at `path` for one read-modify-write: the
+/// in-process mutex plus an exclusive lock on a file beside the store, so a
+/// `--job` run and the app can't interleave either. Both go on drop.
+fn lock_counters(path: &Path) -> io::Result<(MutexGuard<'static, ()>, fs::File)> {
+    let guard = COUNTER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock"))?;
+    file.lock()?;
+    Ok((guard, file))
+}
+
+/// Next number `key` will hand out; new counters start at 1.
+pub(super) fn peek_counter(key: &str) -> Result<u64, NameError> {
+    let path = counter_file()?;
+    let _lock = lock_counters(&path).map_err(counter_io_error)?;
+    let counters = load_counters(&path).map_err(counter_io_error)?;
+    Ok(counters.get(key).copied().unwrap_or(1))
+}
+
+/// Take the next `count` numbers of `key` for one batch and return the
+/// first. They're the batch's alone, whoever else renames meanwhile.
+pub(super) fn reserve_counter(key: &str, count: u64) -> Result<u64, NameError> {
+    let path = counter_file()?;
+    let _lock = lock_counters(&path).map_err(counter_io_error)?;
+    let mut counters = load_counters(&path).map_err(counter_io_error)?;
+    let next = counters.entry(key.to_string()).or_insert(1);
+    let start = *next;
+    *next = start.saturating_add(count);
+    save_counters(&path, &counters).map_err(counter_io_error)?;
+    Ok(start)
+}
+
+/// Hand back the numbers of a reservation ending at `end` from `next` on,
+/// unless a later reservation has already moved `key` past it.
+pub(super) fn release_counter(key: &str, end: u64, next: u64) -> Result<(), NameError> {
+    let path = counter_file()?;
+    let _lock = lock_counters(&path).map_err(counter_io_error)?;
+    let mut counters = load_counters(&path).map_err(counter_io_error)?;
+    match counters.get_mut(key) {
+        Some(current) if *current == end && next < end => *current = next,
+        _ => return Ok(()),
+    }
+    save_counters(&path, &counters).map_err(counter_io_error)
+}
+
+/// Set the next number `key` will hand out.
+pub fn reset_counter(key: String, value: u64) -> Result<(), String> {
+    let path = counter_file().map_err(|e| e.message)?;
+    let _lock = lock_counters(&path).map_err(|e| e.to_string())?;
+    let mut counters = load_counters(&path).map_err(|e| e.to_string())?;
+    counters.insert(key, value);
+    save_counters(&path, &counters).map_err(|e| e.to_string())
+}
+
+/// The `Counter` key of `cmd`, if it is one.
+pub(super) fn counter_key<'a>(cmd: &'a PreparedCommand) -> Option<&'a str> {
+    match cmd.cmd {
+        RenameCommand::Counter { key, .. } => Some(key),
+        _ => None,
+    }
+}
+
+/// Reserve the numbers the `Counter` commands in `used` will hand out, so no
+/// other batch, in this process or another, gets them. Commands sharing a key
+/// share one range, in which the `index`-th file gets `start + index`.
+/// Returns each key's reserved range for [`commit_counters`].
+pub(super) fn reserve_counters(used: &[(&PreparedCommand, usize)]) -> HashMap<String, (u64, u64)> {
+    let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+    for (cmd, index) in used {
+        if let Some(key) = counter_key(cmd) {
+            let count = counts.entry(key).or_default();
+            *count = (*count).max(*index as u64 + 1);
+        }
+    }
+
+    let mut ranges = HashMap::new();
+    for (key, count) in counts {
+        let start = reserve_counter(key, count);
+        if let Ok(start) = start {
+            ranges.insert(key.to_string(), (start, start.saturating_add(count)));
+        }
+        for (cmd, _) in used.iter().filter(|(cmd, _)| counter_key(cmd) == Some(key)) {
+            *cmd.counter_start.borrow_mut() = Some(start.clone());
+        }
+    }
+    ranges
+}
+
+/// Give back the reserved counter numbers that no successful rename used, as
+/// far as nobody reserved after them. A failed update doesn't undo the
+/// renames; it's reported as a warning on each that used the counter.
+pub(super) fn commit_counters(
+    used: &[(&PreparedCommand, usize)],
+    results: &mut [RenameResult],
+    ranges: HashMap<String, (u64, u64)>,
+) {
+    for (key, (start, end)) in ranges {
+        let consumed: Vec<(usize, u64)> = used
+            .iter()
+            .zip(results.iter())
+            .enumerate()
+            .filter(|(_, ((cmd, _), res))| {
+                res.code == ResultCode::Success && counter_key(cmd) == Some(key.as_str())
+            })
+            .filter_map(|(i, ((cmd, index), _))| Some((i, cmd.counter_value(*index).ok()?)))
+            .collect();
+        let next = consumed.iter().map(|(_, n)| n + 1).max().unwrap_or(start);
+        if let Err(e) = release_counter(&key, end, next) {
+            for (i, _) in consumed {
+                results[i].add_warning(e.message.clone());
+            }
+        }
+    }
+}
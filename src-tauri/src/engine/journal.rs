@@ -0,0 +1,363 @@
+// The rename journal and `.origname` sidecars, and undoing renames from
+// either.
+
+use super::*;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// --- Journal ---
+
+/// One line of the rename journal: full paths before and after, and the id of
+/// the call that made it. Journals written before batch ids have no `batch`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub(super) old: String,
+    pub(super) new: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) batch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) op: Option<String>,
+}
+
+/// Id shared by every journal entry written during one rename call.
+pub(super) fn new_batch_id() -> String {
+    Utc::now().format("%Y%m%dT%H%M%S%.9fZ").to_string()
+}
+
+pub(super) fn append_journal(journal_path: &Path, entry: &JournalEntry) -> io::Result<()> {
+    use std::io::Write;
+
+    let line = serde_json::to_string(entry).map_err(io::Error::other)?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Reverse a single journal entry, renaming `new` back to `old`.
+fn revert_entry(entry: &JournalEntry) -> RenameResult {
+    let current = Path::new(&entry.new);
+    let original = Path::new(&entry.old);
+    let path = entry.new.clone();
+
+    if !current.exists() {
+        return RenameResult::failure(path, ResultCode::NotFound, "Renamed file missing");
+    }
+    if original.exists() {
+        return RenameResult::failure(
+            path,
+            ResultCode::TargetExists,
+            format!("Target exists: {}", entry.old),
+        );
+    }
+
+    let old_name = original
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let current_name = current
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+
+    match rename_noreplace(current, original) {
+        Ok(_) => {
+            let diff = NameDiff::between(&current_name, &old_name);
+            RenameResult::success(path, old_name, diff)
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => RenameResult::failure(
+            path,
+            ResultCode::TargetExists,
+            format!("Target exists: {}", entry.old),
+        ),
+        Err(e) => RenameResult::failure(path, ResultCode::IoError, e.to_string()),
+    }
+}
+
+/// Undo every rename recorded in a journal, newest first. Entries that can't
+/// be reversed are reported and skipped; the journal file itself is left as is.
+pub fn restore_from_journal(journal_path: String) -> Vec<RenameResult> {
+    let content = match fs::read_to_string(&journal_path) {
+        Ok(c) => c,
+        Err(e) => {
+            return vec![RenameResult::failure(
+                journal_path,
+                ResultCode::IoError,
+                e.to_string(),
+            )]
+        }
+    };
+
+    let lines: Vec<(usize, &str)> = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+
+    lines
+        .into_iter()
+        .rev()
+        .map(
+            |(i, line)| match serde_json::from_str::<JournalEntry>(line) {
+                Ok(entry) => revert_entry(&entry),
+                Err(_) => RenameResult::failure(
+                    journal_path.clone(),
+                    ResultCode::InvalidInput,
+                    format!("Malformed journal line {}", i + 1),
+                ),
+            },
+        )
+        .collect()
+}
+
+/// Extension appended to a renamed file's path for its sidecar.
+const SIDECAR_EXT: &str = ".origname";
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_os_string();
+    sidecar.push(SIDECAR_EXT);
+    PathBuf::from(sidecar)
+}
+
+/// Whether `path` is a sidecar. Sidecars follow their file around, so batches
+/// leave them out rather than renaming them on their own.
+pub(super) fn is_sidecar(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().ends_with(SIDECAR_EXT.as_bytes()))
+}
+
+/// Record `old_path`'s name beside `new_path`. A file renamed again keeps the
+/// name from its previous sidecar, which is moved rather than left stale. An
+/// existing sidecar at the new name is never overwritten.
+pub(super) fn write_sidecar(old_path: &Path, new_path: &Path) -> io::Result<()> {
+    use std::io::Write;
+
+    let old_sidecar = sidecar_path(old_path);
+    let original = match fs::read_to_string(&old_sidecar) {
+        Ok(original) => original,
+        Err(_) => old_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    let new_sidecar = sidecar_path(new_path);
+    let mut file = fs::File::create_new(&new_sidecar).map_err(|e| {
+        if e.kind() == io::ErrorKind::AlreadyExists {
+            io::Error::new(
+                e.kind(),
+                format!("{} already exists", new_sidecar.display()),
+            )
+        } else {
+            e
+        }
+    })?;
+    file.write_all(original.as_bytes())?;
+    if old_sidecar.exists() {
+        fs::remove_file(old_sidecar)?;
+    }
+    Ok(())
+}
+
+/// Give every file below `dir` that has a sidecar the name recorded in it,
+/// within the file's current folder, and delete the sidecars that were used.
+pub fn restore_from_sidecars(dir: String) -> Vec<RenameResult> {
+    let mut files = Vec::new();
+    if let Err(e) = collect_files(Path::new(&dir), None, &mut files) {
+        return vec![RenameResult::failure(
+            dir,
+            ResultCode::IoError,
+            e.to_string(),
+        )];
+    }
+    files.sort();
+
+    files
+        .iter()
+        .filter_map(|sidecar| {
+            let name = sidecar.file_name()?.to_str()?;
+            let renamed = sidecar.with_file_name(name.strip_suffix(SIDECAR_EXT)?);
+            Some((sidecar, renamed))
+        })
+        .map(|(sidecar, renamed)| {
+            let path = renamed.to_string_lossy().into_owned();
+            let original = match fs::read_to_string(sidecar) {
+                Ok(original) => original,
+                Err(e) => return RenameResult::failure(path, ResultCode::IoError, e.to_string()),
+            };
+            if !check_name(&original, cfg!(windows)).valid {
+                return RenameResult::failure(path, ResultCode::InvalidInput, "Malformed sidecar");
+            }
+            let entry = JournalEntry {
+                old: renamed
+                    .with_file_name(&original)
+                    .to_string_lossy()
+                    .into_owned(),
+                new: path,
+                batch: None,
+                op: None,
+            };
+            let mut res = revert_entry(&entry);
+            if res.code == ResultCode::Success {
+                if let Err(e) = fs::remove_file(sidecar) {
+                    res.add_warning(format!("Sidecar removal failed: {}", e));
+                }
+            }
+            res
+        })
+        .collect()
+}
+
+/// Journal entries with their 0-based line numbers, skipping blank lines.
+fn parse_journal(content: &str) -> Result<Vec<(usize, JournalEntry)>, NameError> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map(|entry| (i, entry))
+                .map_err(|_| {
+                    NameError::new(
+                        ResultCode::InvalidInput,
+                        format!("Malformed journal line {}", i + 1),
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Revert `entries` newest first, then rewrite the journal without the ones
+/// that were reverted so they can't be applied twice. Entries that fail stay
+/// for a later retry.
+fn revert_and_consume<'a>(
+    journal_path: &str,
+    content: &str,
+    entries: impl DoubleEndedIterator<Item = &'a (usize, JournalEntry)>,
+) -> (Vec<RenameResult>, io::Result<()>) {
+    let mut reverted = HashSet::new();
+    let results: Vec<RenameResult> = entries
+        .rev()
+        .map(|(i, entry)| {
+            let mut res = revert_entry(entry);
+            if res.code == ResultCode::Success {
+                reverted.insert(*i);
+                res.op_id = entry.op.clone();
+            }
+            res
+        })
+        .collect();
+
+    let kept: String = content
+        .lines()
+        .enumerate()
+        .filter(|(i, line)| !reverted.contains(i) && !line.trim().is_empty())
+        .map(|(_, line)| format!("{}\n", line))
+        .collect();
+    (results, fs::write(journal_path, kept))
+}
+
+/// Outcome of [`undo_last_batch`]. `batch` is the id of the batch that was
+/// reverted, so the UI can confirm what it undid.
+#[derive(Serialize, Deserialize)]
+pub struct UndoResult {
+    pub(super) code: ResultCode,
+    pub(super) status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) batch: Option<String>,
+    pub(super) results: Vec<RenameResult>,
+}
+
+impl UndoResult {
+    pub(super) fn failure(code: ResultCode, status: impl Into<String>) -> Self {
+        UndoResult {
+            code,
+            status: status.into(),
+            batch: None,
+            results: Vec::new(),
+        }
+    }
+}
+
+/// Revert the newest batch in a journal, newest entry first, then remove the
+/// reverted entries from the journal so a second call can't apply them again.
+/// Entries that fail to revert stay in the journal for a later retry.
+pub fn undo_last_batch(journal_path: String) -> UndoResult {
+    let content = match fs::read_to_string(&journal_path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return UndoResult::failure(ResultCode::NothingToUndo, "Nothing to undo")
+        }
+        Err(e) => return UndoResult::failure(ResultCode::IoError, e.to_string()),
+    };
+
+    let entries = match parse_journal(&content) {
+        Ok(entries) => entries,
+        Err(e) => return UndoResult::failure(e.code, e.message),
+    };
+    let Some((_, last)) = entries.last() else {
+        return UndoResult::failure(ResultCode::NothingToUndo, "Nothing to undo");
+    };
+    let batch = last.batch.clone();
+
+    let chosen = entries.iter().filter(|(_, entry)| entry.batch == batch);
+    let (results, written) = revert_and_consume(&journal_path, &content, chosen);
+    if let Err(e) = written {
+        return UndoResult {
+            code: ResultCode::IoError,
+            status: format!("Journal update failed: {}", e),
+            batch,
+            results,
+        };
+    }
+
+    UndoResult {
+        code: ResultCode::Success,
+        status: "Success".to_string(),
+        batch,
+        results,
+    }
+}
+
+/// Revert only the journaled renames whose `op_id` is listed, newest first,
+/// and remove them from the journal. Ids not in the journal (unknown or
+/// already undone) are reported as `NotFound`.
+pub fn undo_ops(journal_path: String, op_ids: Vec<String>) -> Vec<RenameResult> {
+    let journal_failure =
+        |code, status: String| vec![RenameResult::failure(journal_path.clone(), code, status)];
+    let content = match fs::read_to_string(&journal_path) {
+        Ok(c) => c,
+        Err(e) => return journal_failure(ResultCode::IoError, e.to_string()),
+    };
+    let entries = match parse_journal(&content) {
+        Ok(entries) => entries,
+        Err(e) => return journal_failure(e.code, e.message),
+    };
+
+    let wanted: HashSet<&str> = op_ids.iter().map(String::as_str).collect();
+    let chosen = entries
+        .iter()
+        .filter(|(_, entry)| entry.op.as_deref().is_some_and(|op| wanted.contains(op)));
+    let (mut results, written) = revert_and_consume(&journal_path, &content, chosen);
+    if let Err(e) = written {
+        for res in results.iter_mut().filter(|r| r.code == ResultCode::Success) {
+            res.warning = Some(format!("Journal update failed: {}", e));
+        }
+    }
+
+    let known: HashSet<&str> = entries
+        .iter()
+        .filter_map(|(_, e)| e.op.as_deref())
+        .collect();
+    results.extend(
+        op_ids
+            .iter()
+            .filter(|id| !known.contains(id.as_str()))
+            .map(|id| RenameResult::failure(id.clone(), ResultCode::NotFound, "Unknown op id")),
+    );
+    results
+}
@@ -0,0 +1,2818 @@
+// Computing a new name for one file: validation, safe mode, the text
+// helpers behind each command, and planning a single rename.
+
+use super::*;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use unicode_normalization::UnicodeNormalization;
+
+// --- Safe mode ---
+
+/// Safe mode blocks renames whose stem ends up this many characters or shorter.
+const SAFE_MODE_MIN_STEM_LEN: usize = 1;
+
+/// Safe mode blocks renames that change more than this fraction of the name.
+const SAFE_MODE_MAX_CHANGE: f64 = 0.8;
+
+/// Fraction (0.0-1.0) of the name that differs between `old` and `new`,
+/// measured as the changed middle relative to the longer name.
+fn change_fraction(old: &str, new: &str) -> f64 {
+    let longest = old.chars().count().max(new.chars().count());
+    if longest == 0 {
+        return 0.0;
+    }
+    let diff = NameDiff::between(old, new);
+    let changed = diff.removed.chars().count().max(diff.added.chars().count());
+    changed as f64 / longest as f64
+}
+
+/// Reason safe mode would refuse renaming `old` to `new`, if any:
+/// - the extension is dropped,
+/// - the stem shrinks to [`SAFE_MODE_MIN_STEM_LEN`] characters or fewer,
+/// - more than [`SAFE_MODE_MAX_CHANGE`] of the name changes.
+pub(super) fn safe_mode_violation(old: &str, new: &str) -> Option<String> {
+    let old_path = Path::new(old);
+    let new_path = Path::new(new);
+
+    if old_path.extension().is_some() && new_path.extension().is_none() {
+        return Some("extension removed".into());
+    }
+
+    let stem_len = |p: &Path| {
+        p.file_stem()
+            .map_or(0, |s| s.to_string_lossy().chars().count())
+    };
+    let new_stem_len = stem_len(new_path);
+    if new_stem_len <= SAFE_MODE_MIN_STEM_LEN && stem_len(old_path) > new_stem_len {
+        return Some(format!("name reduced to {} character(s)", new_stem_len));
+    }
+
+    let fraction = change_fraction(old, new);
+    if fraction > SAFE_MODE_MAX_CHANGE {
+        return Some(format!("{:.0}% of the name changed", fraction * 100.0));
+    }
+    None
+}
+
+/// How alarming renaming `old` to `new` looks to a reviewer, from 0.0 to 1.0:
+/// half from [`change_fraction`], 0.3 for a changed extension and the rest
+/// from how much shorter the name gets.
+pub(super) fn risk_score(old: &str, new: &str) -> f64 {
+    let ext = |name: &str| {
+        Path::new(name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+    };
+    let ext_changed = if ext(old) != ext(new) { 1.0 } else { 0.0 };
+
+    let (old_len, new_len) = (old.chars().count(), new.chars().count());
+    let shrink = if old_len == 0 {
+        0.0
+    } else {
+        old_len.saturating_sub(new_len) as f64 / old_len as f64
+    };
+
+    0.5 * change_fraction(old, new) + 0.3 * ext_changed + 0.2 * shrink
+}
+
+// --- Name validation ---
+
+/// Longest file name accepted by common filesystems (bytes on Unix,
+/// UTF-16 code units on Windows).
+const MAX_NAME_LEN: usize = 255;
+
+/// Characters Windows refuses in file names, in addition to ASCII controls.
+const WINDOWS_ILLEGAL_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names Windows reserves regardless of extension (`CON.txt` is invalid too).
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Every problem found with a proposed file name. `valid` is true only when
+/// no issue was found.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct NameValidation {
+    pub(super) valid: bool,
+    pub(super) empty: bool,
+    pub(super) illegal_chars: Vec<char>,
+    pub(super) reserved: bool,
+    pub(super) too_long: bool,
+    pub(super) trailing_dot_or_space: bool,
+}
+
+impl NameValidation {
+    /// Human-readable summary of the first issue, for `RenameResult::status`.
+    pub(super) fn message(&self) -> Option<String> {
+        if self.empty {
+            Some("Resulting name is empty".into())
+        } else if !self.illegal_chars.is_empty() {
+            let chars: String = self.illegal_chars.iter().collect();
+            Some(format!("Illegal characters in name: {:?}", chars))
+        } else if self.reserved {
+            Some("Reserved name".into())
+        } else if self.too_long {
+            Some(format!("Name exceeds {} characters", MAX_NAME_LEN))
+        } else if self.trailing_dot_or_space {
+            Some("Name ends with a dot or space".into())
+        } else {
+            None
+        }
+    }
+}
+
+/// Check `name` against the rules of Windows (`windows = true`) or Unix.
+pub(super) fn check_name(name: &str, windows: bool) -> NameValidation {
+    let mut illegal_chars: Vec<char> = Vec::new();
+    for c in name.chars() {
+        let illegal = if windows {
+            (c.is_ascii_control() && c != '\u{7F}') || WINDOWS_ILLEGAL_CHARS.contains(&c)
+        } else {
+            c == '/' || c == '\0'
+        };
+        if illegal && !illegal_chars.contains(&c) {
+            illegal_chars.push(c);
+        }
+    }
+
+    let reserved = name == "."
+        || name == ".."
+        || windows && {
+            let base = name.split('.').next().unwrap_or("").trim_end();
+            WINDOWS_RESERVED_NAMES
+                .iter()
+                .any(|r| r.eq_ignore_ascii_case(base))
+        };
+
+    let len = if windows {
+        name.encode_utf16().count()
+    } else {
+        name.len()
+    };
+
+    let mut validation = NameValidation {
+        valid: false,
+        empty: name.is_empty(),
+        illegal_chars,
+        reserved,
+        too_long: len > MAX_NAME_LEN,
+        trailing_dot_or_space: windows && !reserved && (name.ends_with('.') || name.ends_with(' ')),
+    };
+    validation.valid = validation.message().is_none();
+    validation
+}
+
+/// Reject `Wrap` strings the filesystem can't hold, before any name is built,
+/// so every file fails with the wrapper named rather than its own result.
+fn check_wrapper(open: &str, close: &str) -> Result<(), NameError> {
+    let illegal = check_name(&format!("{}{}", open, close), cfg!(windows)).illegal_chars;
+    if illegal.is_empty() {
+        Ok(())
+    } else {
+        let chars: String = illegal.iter().collect();
+        Err(NameError::new(
+            ResultCode::InvalidInput,
+            format!("Illegal characters in wrapper: {:?}", chars),
+        ))
+    }
+}
+
+/// Validate a user-typed target name for the current platform without
+/// touching the filesystem.
+pub fn validate_name(name: String) -> NameValidation {
+    check_name(&name, cfg!(windows))
+}
+
+// --- Character width conversion helpers ---
+
+fn to_zenkaku(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            ' ' => '\u{3000}',
+            '!'..='~' => char::from_u32(c as u32 + 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+pub(super) fn to_hankaku(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\u{3000}' => ' ',
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// Japanese punctuation and its half-width form, which `Convert` misses
+/// because the pairs sit outside the U+FF01..U+FF5E block. ASCII look-alikes
+/// (`,` `.` `[` `]`) aren't used: `.` in particular already means something
+/// in a file name.
+const JA_PUNCTUATION: [(char, char); 5] = [
+    ('、', '､'),
+    ('。', '｡'),
+    ('「', '｢'),
+    ('」', '｣'),
+    ('・', '･'),
+];
+
+fn convert_ja_punctuation(s: &str, to: &WidthMode) -> String {
+    s.chars()
+        .map(|c| {
+            JA_PUNCTUATION
+                .iter()
+                .find_map(|&(full, half)| match to {
+                    WidthMode::Zenkaku => (c == half).then_some(full),
+                    WidthMode::Hankaku => (c == full).then_some(half),
+                })
+                .unwrap_or(c)
+        })
+        .collect()
+}
+
+/// Unicode bidi controls: LRM, RLM, ALM, LRE..RLO and LRI..PDI. An RLO can
+/// make `report\u{202E}fdp.exe` display as `reportexe.pdf`.
+fn is_bidi_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200E}' | '\u{200F}' | '\u{061C}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+/// Apply the `Convert` width conversion to arbitrary text, so the UI can
+/// preview it live without a file.
+pub fn convert_width(text: String, mode: WidthMode) -> String {
+    match mode {
+        WidthMode::Zenkaku => to_zenkaku(&text),
+        WidthMode::Hankaku => to_hankaku(&text),
+    }
+}
+
+/// Uppercase the first character and every character that directly follows
+/// one of `delimiters`. All other characters are left untouched.
+pub(super) fn capitalize_after(s: &str, delimiters: &[char]) -> String {
+    let mut capitalize_next = true;
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if capitalize_next {
+            out.extend(c.to_uppercase());
+        } else {
+            out.push(c);
+        }
+        capitalize_next = delimiters.contains(&c);
+    }
+    out
+}
+
+/// Insert `separator` between every group of three digits, counting from the right.
+fn regroup(digits: &str, separator: &str) -> String {
+    if separator.is_empty() {
+        return digits.to_string();
+    }
+    let len = digits.len();
+    let mut out = String::with_capacity(len + len / 3 * separator.len());
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (len - i).is_multiple_of(3) {
+            out.push_str(separator);
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Regroup every digit run in `s` into thousands: `1000000` becomes `1,000,000`
+/// with `separator = ","`, and an empty separator removes grouping instead.
+///
+/// Existing grouping written with any char in `strip` is removed first, but only
+/// when it looks like real thousands grouping (1-3 digits, then groups of exactly
+/// three). Other runs such as the date `2023_01_05` are regrouped piece by piece
+/// and keep their separators.
+///
+/// Separator safety: `,` `_` `.` `'` and space are legal in names on Windows,
+/// macOS and Linux, but `.` confuses extension parsing, `'` and `,` need quoting
+/// in many shells and CSV tools, and space breaks naive scripts. `_` is the
+/// safest choice; no separator at all is the default in the UI.
+pub(super) fn group_digits(s: &str, separator: &str, strip: &[char]) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::with_capacity(s.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        if !chars[i].is_ascii_digit() {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        // Collect digit pieces joined by strip chars: "1,000,000" -> ["1", "000", "000"]
+        let mut pieces: Vec<String> = Vec::new();
+        let mut seps: Vec<char> = Vec::new();
+        loop {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            pieces.push(chars[start..i].iter().collect());
+
+            let joins_next =
+                i + 1 < chars.len() && strip.contains(&chars[i]) && chars[i + 1].is_ascii_digit();
+            if !joins_next {
+                break;
+            }
+            seps.push(chars[i]);
+            i += 1;
+        }
+
+        let is_grouped =
+            pieces.len() > 1 && pieces[0].len() <= 3 && pieces[1..].iter().all(|p| p.len() == 3);
+        if is_grouped {
+            out.push_str(&regroup(&pieces.concat(), separator));
+        } else {
+            for (n, piece) in pieces.iter().enumerate() {
+                if n > 0 {
+                    out.push(seps[n - 1]);
+                }
+                out.push_str(&regroup(piece, separator));
+            }
+        }
+    }
+    out
+}
+
+/// Collapse consecutive runs of the same character into one, for each char in
+/// `chars`. Runs of different characters (`_-_`) are left alone.
+pub(super) fn collapse_repeats(s: &str, chars: &[char]) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut prev: Option<char> = None;
+    for c in s.chars() {
+        if prev == Some(c) && chars.contains(&c) {
+            continue;
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+    out
+}
+
+/// Characters treated as word separators around numbers (`MoveNumber`) and
+/// around removed words (`RemoveWords`).
+const NUMBER_SEPARATORS: &[char] = &['_', '-', ' ', '.'];
+
+/// One whole emoji sequence: a flag pair, a keycap, or a pictograph with its
+/// skin-tone modifiers, variation selectors, tag characters and any
+/// ZWJ-joined parts. Text-style symbols such as `©` and `™` are left alone.
+const EMOJI_PATTERN: &str = r"(?x)
+    \p{Regional_Indicator}{1,2}
+    | [0-9\#*]\x{FE0F}?\x{20E3}
+    | (?:
+        [\p{Extended_Pictographic}--[\x{A9}\x{AE}\x{203C}\x{2049}\x{2122}\x{2139}]]
+        [\p{Emoji_Modifier}\x{FE0F}\x{E0020}-\x{E007F}]*
+      )
+      (?:
+        \x{200D}
+        \p{Extended_Pictographic}
+        [\p{Emoji_Modifier}\x{FE0F}\x{E0020}-\x{E007F}]*
+      )*
+";
+
+/// Web-safe slug of `stem`: accents folded, lowercase ASCII letters and digits
+/// only, every other run of characters collapsed into one `separator`.
+/// `max_length` (in chars) cuts at the last separator that fits, or mid-word
+/// when even the first word is too long.
+pub(super) fn slugify(stem: &str, separator: &str, max_length: Option<usize>) -> String {
+    let folded: String = stem
+        .nfkd()
+        .filter(|c| !unicode_normalization::char::is_combining_mark(*c))
+        .collect();
+    let words: Vec<String> = folded
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_ascii_lowercase())
+        .collect();
+
+    let Some(max) = max_length else {
+        return words.join(separator);
+    };
+    let sep_len = separator.chars().count();
+    let mut slug = String::new();
+    let mut len = 0;
+    for word in &words {
+        let extra = if slug.is_empty() { 0 } else { sep_len };
+        if len + extra + word.len() > max {
+            if slug.is_empty() {
+                slug = word[..max.min(word.len())].to_string();
+            }
+            break;
+        }
+        if extra > 0 {
+            slug.push_str(separator);
+        }
+        slug.push_str(word);
+        len += extra + word.len();
+    }
+    slug
+}
+
+/// `to` in the case pattern of `matched`: all upper, all lower or title
+/// case (`Color`). Any other mix keeps `to` as written.
+pub(super) fn match_case(matched: &str, to: &str) -> String {
+    let has_upper = matched.chars().any(char::is_uppercase);
+    let has_lower = matched.chars().any(char::is_lowercase);
+    let mut chars = matched.chars();
+    let title = chars.next().is_some_and(char::is_uppercase) && !chars.any(char::is_uppercase);
+    if has_upper && !has_lower {
+        to.to_uppercase()
+    } else if has_lower && !has_upper {
+        to.to_lowercase()
+    } else if title {
+        let mut to_chars = to.chars();
+        match to_chars.next() {
+            Some(first) => first
+                .to_uppercase()
+                .chain(to_chars.flat_map(char::to_lowercase))
+                .collect(),
+            None => String::new(),
+        }
+    } else {
+        to.to_string()
+    }
+}
+
+/// Char range of the first ASCII digit run in `chars`.
+fn first_digit_run(chars: &[char]) -> Option<(usize, usize)> {
+    let start = chars.iter().position(|c| c.is_ascii_digit())?;
+    let len = chars[start..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    Some((start, start + len))
+}
+
+/// Add `delta` to the first digit run of `stem`, clamping at 0, and rewrite it
+/// zero-padded to `pad` (`ep05` + 10 -> `ep15`). `None` if there is no number.
+pub(super) fn shift_number(
+    stem: &str,
+    delta: i64,
+    pad: usize,
+) -> Option<Result<String, NameError>> {
+    let chars: Vec<char> = stem.chars().collect();
+    let (start, end) = first_digit_run(&chars)?;
+
+    let digits: String = chars[start..end].iter().collect();
+    let Ok(n) = digits.parse::<u64>() else {
+        return Some(Err(NameError::new(
+            ResultCode::InvalidInput,
+            "Number too large to shift",
+        )));
+    };
+    let shifted = (i128::from(n) + i128::from(delta)).max(0);
+
+    let before: String = chars[..start].iter().collect();
+    let after: String = chars[end..].iter().collect();
+    Some(Ok(format!(
+        "{}{:0width$}{}",
+        before,
+        shifted,
+        after,
+        width = pad
+    )))
+}
+
+/// Rewrite the digit run ending `stem` zero-padded to `width`, dropping any
+/// extra leading zeros (`scan_7` -> `scan_007`, `scan_00042` -> `scan_042`).
+/// A number wider than `width` is kept whole; a stem without one is unchanged.
+fn repad_trailing_number(stem: &str, width: usize) -> String {
+    let head = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = &stem[head.len()..];
+    if digits.is_empty() {
+        return stem.to_string();
+    }
+    let significant = match digits.trim_start_matches('0') {
+        "" => "0",
+        rest => rest,
+    };
+    format!("{}{:0>width$}", head, significant, width = width)
+}
+
+/// Left-pad the first digit run of `stem` with zeros to `width` digits.
+/// Works on the digits as text, so any length of number is fine. `None` if
+/// there is no number.
+fn pad_first_number(stem: &str, width: usize) -> Option<String> {
+    let chars: Vec<char> = stem.chars().collect();
+    let (start, end) = first_digit_run(&chars)?;
+    let zeros = width.saturating_sub(end - start);
+
+    let mut out: String = chars[..start].iter().collect();
+    out.extend(std::iter::repeat_n('0', zeros));
+    out.extend(&chars[start..]);
+    Some(out)
+}
+
+/// Pattern for `RemoveWords`: the words as literal alternatives, longest
+/// first so `theme` wins over `the`. With `whole_word` it must match a whole
+/// separator-delimited token.
+pub(super) fn remove_words_regex(
+    words: &[String],
+    whole_word: bool,
+    ignore_case: bool,
+) -> Result<Regex, NameError> {
+    let mut words: Vec<&str> = words
+        .iter()
+        .map(String::as_str)
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.is_empty() {
+        return Err(NameError::new(
+            ResultCode::InvalidInput,
+            "No words to remove",
+        ));
+    }
+    words.sort_by_key(|w| std::cmp::Reverse(w.len()));
+    let alternatives: Vec<String> = words.iter().map(|w| regex::escape(w)).collect();
+    let pattern = if whole_word {
+        format!("^(?:{})$", alternatives.join("|"))
+    } else {
+        alternatives.join("|")
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| NameError::new(ResultCode::InvalidInput, format!("Regex error: {}", e)))
+}
+
+/// Drop every `re` match from `stem` (each whole token when `whole_word`)
+/// along with the separators it leaves dangling: at either end of the stem,
+/// or doubled up where the match sat between two separator runs.
+pub(super) fn remove_words(stem: &str, re: &Regex, whole_word: bool) -> String {
+    let is_sep = |c: char| NUMBER_SEPARATORS.contains(&c);
+    let ranges: Vec<(usize, usize)> = if whole_word {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        for (i, c) in stem
+            .char_indices()
+            .chain(std::iter::once((stem.len(), ' ')))
+        {
+            if is_sep(c) {
+                if start < i && re.is_match(&stem[start..i]) {
+                    ranges.push((start, i));
+                }
+                start = i + c.len_utf8();
+            }
+        }
+        ranges
+    } else {
+        re.find_iter(stem).map(|m| (m.start(), m.end())).collect()
+    };
+
+    let mut out = String::with_capacity(stem.len());
+    let mut last = 0;
+    for (start, end) in ranges {
+        if start < last {
+            continue;
+        }
+        out.push_str(&stem[last..start]);
+        let rest = &stem[end..];
+        let after_seps = rest.trim_start_matches(is_sep);
+        last = if out.is_empty() || (out.ends_with(is_sep) && after_seps.len() < rest.len()) {
+            stem.len() - after_seps.len()
+        } else {
+            end
+        };
+        if last == stem.len() {
+            out.truncate(out.trim_end_matches(is_sep).len());
+        }
+    }
+    out.push_str(&stem[last..]);
+    out
+}
+
+/// `stem` with `separator` between each adjacent letter and digit. Any
+/// Unicode letter or numeric character counts, so `第3章` splits too.
+fn separate_alnum(stem: &str, separator: &str) -> String {
+    let mut out = String::with_capacity(stem.len());
+    let mut prev: Option<char> = None;
+    for c in stem.chars() {
+        if let Some(p) = prev {
+            let boundary =
+                (p.is_alphabetic() && c.is_numeric()) || (p.is_numeric() && c.is_alphabetic());
+            if boundary {
+                out.push_str(separator);
+            }
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+    out
+}
+
+/// Move the first digit run of `stem` to the start or end, taking one adjacent
+/// separator along with it (`Document_001` -> `001_Document`). Falls back to `_`
+/// when the number had no separator next to it. Returns `None` if there is no number.
+pub(super) fn move_number(stem: &str, to: &Position) -> Option<String> {
+    let chars: Vec<char> = stem.chars().collect();
+    let (start, end) = first_digit_run(&chars)?;
+
+    let already_placed = match to {
+        Position::Start => start == 0,
+        Position::End => end == chars.len(),
+    };
+    if already_placed {
+        return Some(stem.to_string());
+    }
+
+    let number: String = chars[start..end].iter().collect();
+    let (cut_start, cut_end, sep) = if end < chars.len() && NUMBER_SEPARATORS.contains(&chars[end])
+    {
+        (start, end + 1, chars[end])
+    } else if start > 0 && NUMBER_SEPARATORS.contains(&chars[start - 1]) {
+        (start - 1, end, chars[start - 1])
+    } else {
+        (start, end, '_')
+    };
+    let rest: String = chars[..cut_start].iter().chain(&chars[cut_end..]).collect();
+
+    Some(match to {
+        Position::Start => format!("{}{}{}", number, sep, rest),
+        Position::End => format!("{}{}{}", rest, sep, number),
+    })
+}
+
+// --- Date helpers ---
+
+/// Format `dt` with a strftime pattern, rejecting malformed patterns up front
+/// (chrono would otherwise panic while formatting).
+pub(super) fn format_datetime(dt: &NaiveDateTime, format: &str) -> Result<String, NameError> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(NameError::new(
+            ResultCode::InvalidInput,
+            format!("Invalid date format: {}", format),
+        ));
+    }
+    Ok(dt.format(format).to_string())
+}
+
+// --- Name tokens ---
+
+/// Format used by a bare `{date}` token.
+const DEFAULT_DATE_TOKEN_FORMAT: &str = "%Y-%m-%d";
+
+/// First non-empty environment variable among `vars`.
+fn env_first(vars: &[&str]) -> Option<String> {
+    vars.iter()
+        .filter_map(|v| std::env::var(v).ok())
+        .find(|v| !v.is_empty())
+}
+
+fn current_user() -> Option<String> {
+    env_first(&["USER", "USERNAME", "LOGNAME"])
+}
+
+fn current_hostname() -> Option<String> {
+    env_first(&["HOSTNAME", "COMPUTERNAME"]).or_else(|| {
+        ["/etc/hostname", "/proc/sys/kernel/hostname"]
+            .iter()
+            .filter_map(|p| fs::read_to_string(p).ok())
+            .map(|s| s.trim().to_string())
+            .find(|s| !s.is_empty())
+    })
+}
+
+/// Expand `{date}`, `{date:FORMAT}`, `{user}` and `{hostname}` in `template`.
+/// `{{` and `}}` stand for literal braces; anything else in braces is an error.
+pub(super) fn expand_tokens(template: &str) -> Result<String, NameError> {
+    let invalid = |message: String| NameError::new(ResultCode::InvalidInput, message);
+
+    expand_braces(template, |token| {
+        Ok(match token.split_once(':') {
+            Some(("date", format)) => format_datetime(&Local::now().naive_local(), format)?,
+            None if token == "date" => {
+                format_datetime(&Local::now().naive_local(), DEFAULT_DATE_TOKEN_FORMAT)?
+            }
+            None if token == "user" => {
+                current_user().ok_or_else(|| invalid("User name unavailable".to_string()))?
+            }
+            None if token == "hostname" => {
+                current_hostname().ok_or_else(|| invalid("Hostname unavailable".to_string()))?
+            }
+            _ => return Err(invalid(format!("Unknown token: {{{}}}", token))),
+        })
+    })
+}
+
+/// Replace each `{token}` in `template` with `value_of(token)`; `{{` and
+/// `}}` stand for literal braces.
+fn expand_braces(
+    template: &str,
+    mut value_of: impl FnMut(&str) -> Result<String, NameError>,
+) -> Result<String, NameError> {
+    let invalid = |message: String| NameError::new(ResultCode::InvalidInput, message);
+
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if let Some(after) = tail.strip_prefix("{{").or(tail.strip_prefix("}}")) {
+            out.push_str(&tail[..1]);
+            rest = after;
+            continue;
+        }
+        if tail.starts_with('}') {
+            return Err(invalid("Unmatched '}' in name".to_string()));
+        }
+        let Some(end) = tail.find('}') else {
+            return Err(invalid("Unclosed token in name".to_string()));
+        };
+
+        out.push_str(&value_of(&tail[1..end])?);
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Regex locating text that could match the strftime pattern `format`, used
+/// to find a date inside a longer stem before chrono parses it strictly.
+fn date_pattern_regex(format: &str) -> Result<Regex, NameError> {
+    let mut pattern = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+            continue;
+        }
+        let spec = chars.next();
+        pattern.push_str(match spec {
+            Some('Y') => r"\d{4}",
+            Some('y' | 'C') => r"\d{2}",
+            Some('m' | 'd' | 'H' | 'M' | 'S' | 'I') => r"\d{1,2}",
+            Some('e') => r"[ \d]?\d",
+            Some('j') => r"\d{1,3}",
+            Some('b' | 'h' | 'a') => r"[A-Za-z]{3}",
+            Some('B' | 'A') => r"[A-Za-z]+",
+            Some('p') => r"[AaPp][Mm]",
+            Some('%') => "%",
+            _ => {
+                return Err(NameError::new(
+                    ResultCode::InvalidInput,
+                    format!("Unsupported date pattern: {}", format),
+                ))
+            }
+        });
+    }
+    Regex::new(&pattern)
+        .map_err(|e| NameError::new(ResultCode::InvalidInput, format!("Regex error: {}", e)))
+}
+
+/// Parse `text` with `format` as a date-time, or as a date at midnight when
+/// the pattern has no time fields.
+fn parse_date_with(text: &str, format: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text, format)
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, format)
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })
+}
+
+/// `DateTimeOriginal` from the EXIF block of a JPEG/TIFF/PNG/HEIF file.
+/// Any read or parse failure is treated as "no date".
+fn read_exif_date(path: &Path) -> Option<NaiveDateTime> {
+    let file = fs::File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut io::BufReader::new(file))
+        .ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    let exif::Value::Ascii(ref values) = field.value else {
+        return None;
+    };
+    let dt = exif::DateTime::from_ascii(values.first()?).ok()?;
+
+    NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())?.and_hms_opt(
+        dt.hour.into(),
+        dt.minute.into(),
+        dt.second.into(),
+    )
+}
+
+/// The `MediaTags` fields found in the file's primary tag (or its first one),
+/// keyed by token name. `None` if the file isn't a readable audio file or
+/// carries no tag at all.
+fn read_media_tags(path: &Path) -> Option<HashMap<&'static str, String>> {
+    use lofty::prelude::*;
+
+    let tagged = lofty::read_from_path(path).ok()?;
+    let tag = tagged.primary_tag().or_else(|| tagged.first_tag())?;
+    let mut tags = HashMap::new();
+    let text = [
+        ("artist", tag.artist()),
+        ("album", tag.album()),
+        ("title", tag.title()),
+    ];
+    for (key, value) in text {
+        if let Some(value) = value {
+            tags.insert(key, value.into_owned());
+        }
+    }
+    let numbers = [("track", tag.track()), ("year", tag.year())];
+    for (key, value) in numbers {
+        if let Some(value) = value {
+            tags.insert(key, value.to_string());
+        }
+    }
+    Some(tags)
+}
+
+/// Width and height from the image header, with the format sniffed from the
+/// content rather than trusted from the extension. Pixel data isn't decoded.
+fn read_dimensions(path: &Path) -> Option<(u32, u32)> {
+    image::ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// Make tag text safe to use in a name on any platform: characters Windows
+/// forbids and control characters become `_`. Tags travel with the files,
+/// so this doesn't depend on the platform doing the rename.
+fn sanitize_name_part(text: &str) -> String {
+    text.trim()
+        .chars()
+        .map(|c| {
+            if c.is_control() || WINDOWS_ILLEGAL_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Lowercase hex digest of the file contents, read in chunks so large files
+/// aren't loaded into memory. Gives up once more than `max_bytes` have been
+/// read or the `deadline` passes between chunks.
+fn file_checksum(
+    path: &Path,
+    algo: ChecksumAlgo,
+    max_bytes: Option<u64>,
+    deadline: Option<Instant>,
+) -> Result<String, NameError> {
+    use io::Read;
+    use sha1::Digest;
+
+    let io_error = |e: io::Error| NameError::new(ResultCode::IoError, e.to_string());
+    let too_large = || NameError::new(ResultCode::FileTooLarge, "File too large");
+    let timed_out = || NameError::new(ResultCode::ReadTimeout, "Read timeout");
+
+    let mut file = fs::File::open(path).map_err(io_error)?;
+    // Reject by size up front when the metadata already says so; the running
+    // count below still catches files that grow or report no length
+    if let (Some(max), Ok(meta)) = (max_bytes, file.metadata()) {
+        if meta.is_file() && meta.len() > max {
+            return Err(too_large());
+        }
+    }
+    let mut buf = [0u8; 64 * 1024];
+    let mut crc = crc32fast::Hasher::new();
+    let mut sha = sha1::Sha1::new();
+    let mut total = 0u64;
+    loop {
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(timed_out());
+        }
+        let n = file.read(&mut buf).map_err(io_error)?;
+        if n == 0 {
+            break;
+        }
+        total += n as u64;
+        if max_bytes.is_some_and(|max| total > max) {
+            return Err(too_large());
+        }
+        match algo {
+            ChecksumAlgo::Crc32 => crc.update(&buf[..n]),
+            ChecksumAlgo::Sha1 => sha.update(&buf[..n]),
+        }
+    }
+
+    Ok(match algo {
+        ChecksumAlgo::Crc32 => format!("{:08x}", crc.finalize()),
+        ChecksumAlgo::Sha1 => sha
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    })
+}
+
+/// [`file_checksum`] bounded by `timeout`. The read runs on a worker thread
+/// so a read that blocks outright (a stalled network share, a FIFO with no
+/// writer) still returns `ReadTimeout` on time; the worker stops at its next
+/// chunk or is left to finish in the background.
+pub(super) fn file_checksum_limited(
+    path: &Path,
+    algo: ChecksumAlgo,
+    max_bytes: Option<u64>,
+    timeout: Option<Duration>,
+) -> Result<String, NameError> {
+    let Some(timeout) = timeout else {
+        return file_checksum(path, algo, max_bytes, None);
+    };
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::channel();
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        let _ = tx.send(file_checksum(&path, algo, max_bytes, Some(deadline)));
+    });
+    rx.recv_timeout(timeout)
+        .unwrap_or_else(|_| Err(NameError::new(ResultCode::ReadTimeout, "Read timeout")))
+}
+
+/// 1-based position of `path` among the non-directory entries of its parent,
+/// sorted by file name. Each folder is listed once into `listings`, so a
+/// folder-wide batch doesn't re-read it for every file.
+fn folder_index(
+    path: &Path,
+    listings: &RefCell<HashMap<PathBuf, Vec<OsString>>>,
+) -> io::Result<usize> {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let mut listings = listings.borrow_mut();
+    let names = match listings.entry(parent.to_path_buf()) {
+        Entry::Occupied(e) => e.into_mut(),
+        Entry::Vacant(e) => {
+            let mut names = Vec::new();
+            for entry in fs::read_dir(parent)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() {
+                    names.push(entry.file_name());
+                }
+            }
+            names.sort();
+            e.insert(names)
+        }
+    };
+
+    let own = path.file_name().unwrap_or_default();
+    names
+        .binary_search_by(|name| name.as_os_str().cmp(own))
+        .map(|i| i + 1)
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "File not listed in its folder"))
+}
+
+/// Whether `needle` occurs in `haystack` with no letter or digit right
+/// before or after it, so `IMG_1` is found in `IMG_1 beach` but not `IMG_10`.
+fn contains_token(haystack: &str, needle: &str) -> bool {
+    !needle.is_empty()
+        && haystack.match_indices(needle).any(|(at, _)| {
+            let before = haystack[..at].chars().next_back();
+            let after = haystack[at + needle.len()..].chars().next();
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+}
+
+/// Stem of the one file beside `path` with extension `reference_ext` (any
+/// case) whose stem contains `stem` as a token. A reference with exactly
+/// `stem` wins, since the pair is then already in sync.
+fn sibling_stem(path: &Path, stem: &str, reference_ext: &str) -> Result<String, NameError> {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let entries =
+        fs::read_dir(parent).map_err(|e| NameError::new(ResultCode::IoError, e.to_string()))?;
+
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let sibling = entry.path();
+        let is_reference = sibling
+            .extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(reference_ext));
+        if !is_reference || sibling == path {
+            continue;
+        }
+        if let Some(sibling_stem) = sibling.file_stem().and_then(OsStr::to_str) {
+            if contains_token(sibling_stem, stem) {
+                found.push(sibling_stem.to_string());
+            }
+        }
+    }
+    found.sort();
+
+    if found.iter().any(|s| s == stem) {
+        return Ok(stem.to_string());
+    }
+    match found.as_slice() {
+        [] => Err(NameError::new(
+            ResultCode::NoReference,
+            "No reference found",
+        )),
+        [one] => Ok(one.clone()),
+        several => Err(NameError::new(
+            ResultCode::InvalidInput,
+            format!("Several references found: {}", several.join(", ")),
+        )),
+    }
+}
+
+/// Modification time of `path` in local time.
+fn read_mtime(path: &Path) -> Option<NaiveDateTime> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(DateTime::<Local>::from(modified).naive_local())
+}
+
+/// Split a dotfile stem such as `.gitignore` into `(".", "gitignore")`, so
+/// commands that edit the stem treat the leading dot as a fixed prefix.
+/// Other stems come back as `("", stem)`.
+fn split_leading_dot(stem: &str) -> (&str, &str) {
+    match stem.strip_prefix('.') {
+        Some(rest) if !rest.is_empty() => (".", rest),
+        _ => ("", stem),
+    }
+}
+
+/// Re-split `old_name`, already divided at its last dot into `name_stem` and
+/// `ext`, according to `split`. A dotfile's leading dot is never a split
+/// point, and a name with no extension (or a directory) is left as is.
+fn split_ext<'a>(
+    old_name: &'a str,
+    name_stem: &'a str,
+    ext: &'a str,
+    split: ExtSplit,
+) -> (&'a str, &'a str) {
+    if matches!(split, ExtSplit::LastDot) || ext.is_empty() {
+        return (name_stem, ext);
+    }
+    let (dot, rest) = split_leading_dot(old_name);
+    match rest.find('.') {
+        Some(i) => {
+            let cut = dot.len() + i;
+            (&old_name[..cut], &old_name[cut + 1..])
+        }
+        None => (name_stem, ext),
+    }
+}
+
+/// Drop `start` chars from the front and `end` from the back of `stem`.
+/// At least one char must remain; otherwise `TrimTooLong` reports the
+/// combined count against the stem length.
+fn trim_stem(stem: &str, start: usize, end: usize) -> Result<String, NameError> {
+    let chars: Vec<char> = stem.chars().collect();
+    let len = chars.len();
+    let count = start.saturating_add(end);
+
+    if count >= len {
+        return Err(NameError {
+            code: ResultCode::TrimTooLong,
+            message: format!("Trim count ({}) exceeds name length ({})", count, len),
+            trim_overflow: Some(TrimOverflow { count, len }),
+        });
+    }
+
+    Ok(chars[start..len - end].iter().collect())
+}
+
+/// Reconstruct filename from stem and extension.
+/// If ext is empty, returns just the stem.
+fn join_name_ext(stem: &str, ext: &str) -> String {
+    if ext.is_empty() {
+        stem.to_string()
+    } else {
+        format!("{}.{}", stem, ext)
+    }
+}
+
+// --- Scripts ---
+
+/// Steps a `Script` may run per file before it's stopped as runaway.
+pub(super) const SCRIPT_MAX_OPERATIONS: u64 = 100_000;
+
+/// A Rhai engine with nothing outside the script reachable: no `import`
+/// (the only route to files), no `eval`, output discarded, and limits on
+/// steps, nesting and value sizes.
+fn script_engine() -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine
+        .set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new())
+        .set_max_operations(SCRIPT_MAX_OPERATIONS)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(MAX_NAME_LEN * 4)
+        .set_max_array_size(1024)
+        .set_max_map_size(1024)
+        .on_print(|_| {})
+        .on_debug(|_, _, _| {});
+    engine.disable_symbol("eval");
+    engine
+}
+
+fn compile_script(source: &str) -> Result<(rhai::Engine, rhai::AST), NameError> {
+    let engine = script_engine();
+    let ast = engine
+        .compile(source)
+        .map_err(|e| NameError::new(ResultCode::InvalidInput, format!("Script error: {}", e)))?;
+    Ok((engine, ast))
+}
+
+/// Evaluate a compiled `Script` for one file.
+fn run_script(
+    engine: &rhai::Engine,
+    ast: &rhai::AST,
+    old_path: &Path,
+    old_name: &str,
+    name_stem: &str,
+    ext: &str,
+    index: usize,
+) -> Result<String, NameError> {
+    let parent = old_path
+        .parent()
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut scope = rhai::Scope::new();
+    scope
+        .push_constant("name", old_name.to_string())
+        .push_constant("stem", name_stem.to_string())
+        .push_constant("ext", ext.to_string())
+        .push_constant("index", index as rhai::INT)
+        .push_constant("parent", parent);
+
+    let value: rhai::Dynamic = engine
+        .eval_ast_with_scope(&mut scope, ast)
+        .map_err(|e| NameError::new(ResultCode::InvalidInput, format!("Script error: {}", e)))?;
+    value.into_string().map_err(|type_name| {
+        NameError::new(
+            ResultCode::InvalidInput,
+            format!("Script returned {} instead of a string", type_name),
+        )
+    })
+}
+
+// --- Abbreviation dictionaries ---
+
+/// One direction of an `abbr,full` CSV, ready to apply.
+pub(super) struct Dictionary {
+    /// Alternation of every key, longest first; `None` for an empty file.
+    pub(super) pattern: Option<Regex>,
+    pub(super) map: HashMap<String, String>,
+}
+
+type DictionaryCache = HashMap<(PathBuf, AbbrevDirection), (Option<SystemTime>, Arc<Dictionary>)>;
+
+/// Dictionaries loaded this session. An entry is reused until the file's
+/// modification time changes.
+fn dictionary_cache() -> &'static Mutex<DictionaryCache> {
+    static CACHE: OnceLock<Mutex<DictionaryCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(super) fn parse_dictionary(
+    contents: &str,
+    direction: AbbrevDirection,
+) -> Result<Dictionary, NameError> {
+    let mut map = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((abbr, full)) = line.split_once(',') else {
+            return Err(NameError::new(
+                ResultCode::InvalidInput,
+                format!("Malformed dictionary line {}", i + 1),
+            ));
+        };
+        let (abbr, full) = (abbr.trim().to_string(), full.trim().to_string());
+        match direction {
+            AbbrevDirection::Expand => map.insert(abbr, full),
+            AbbrevDirection::Contract => map.insert(full, abbr),
+        };
+    }
+
+    let mut keys: Vec<&String> = map.keys().filter(|k| !k.is_empty()).collect();
+    keys.sort_by_key(|k| std::cmp::Reverse(k.chars().count()));
+    let pattern = if keys.is_empty() {
+        None
+    } else {
+        let alternation: Vec<String> = keys.iter().map(|k| regex::escape(k)).collect();
+        Some(Regex::new(&alternation.join("|")).map_err(|e| {
+            NameError::new(ResultCode::InvalidInput, format!("Dictionary error: {}", e))
+        })?)
+    };
+    Ok(Dictionary { pattern, map })
+}
+
+/// Load `path` for `direction`, from the session cache when it's current.
+fn load_dictionary(path: &str, direction: AbbrevDirection) -> Result<Arc<Dictionary>, NameError> {
+    let path = PathBuf::from(path);
+    let io_error = |e: io::Error| NameError::new(ResultCode::IoError, e.to_string());
+    let modified = fs::metadata(&path).map_err(io_error)?.modified().ok();
+
+    let key = (path, direction);
+    let mut cache = dictionary_cache().lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached_at, dict)) = cache.get(&key) {
+        if modified.is_some() && *cached_at == modified {
+            return Ok(Arc::clone(dict));
+        }
+    }
+
+    let contents = fs::read_to_string(&key.0).map_err(io_error)?;
+    let dict = Arc::new(parse_dictionary(&contents, direction)?);
+    cache.insert(key, (modified, Arc::clone(&dict)));
+    Ok(dict)
+}
+
+/// Replace whole-word dictionary keys in `stem`. A match only counts when it
+/// isn't glued to another letter or digit, so `Dr` in `Drive` is left alone
+/// while `Dr_Smith` and `Dr Smith` are expanded.
+pub(super) fn apply_dictionary(stem: &str, dict: &Dictionary) -> String {
+    let Some(pattern) = &dict.pattern else {
+        return stem.to_string();
+    };
+    let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+
+    let mut out = String::with_capacity(stem.len());
+    let mut copied = 0;
+    let mut pos = 0;
+    while let Some(m) = pattern.find_at(stem, pos) {
+        let before = stem[..m.start()].chars().next_back();
+        let after = stem[m.end()..].chars().next();
+        if is_word(before) || is_word(after) {
+            // Retry one char further on; a shorter key may still fit
+            pos = m.start() + stem[m.start()..].chars().next().map_or(1, char::len_utf8);
+            continue;
+        }
+        out.push_str(&stem[copied..m.start()]);
+        out.push_str(&dict.map[m.as_str()]);
+        copied = m.end();
+        pos = m.end();
+    }
+    out.push_str(&stem[copied..]);
+    out
+}
+
+// --- Core rename logic ---
+
+/// A [`RenameCommand`] with its regex (if any) compiled up front, so a batch or
+/// preview compiles it once instead of per file. A bad pattern is kept and
+/// reported for each file the command is applied to.
+pub(super) struct PreparedCommand<'a> {
+    pub(super) cmd: &'a RenameCommand,
+    pub(super) regex: Option<Result<Regex, NameError>>,
+    pub(super) dictionary: Option<Result<Arc<Dictionary>, NameError>>,
+    /// First `Counter` value of the batch; file `index` gets `start + index`.
+    /// Previews peek at the store; a real rename replaces it with the start
+    /// of a range [`reserve_counters`] took for the batch.
+    pub(super) counter_start: RefCell<Option<Result<u64, NameError>>>,
+    /// `TimeStamp` names handed out so far in this call, with how many times
+    /// each was used, for `ensure_unique`.
+    stamps_seen: RefCell<HashMap<String, usize>>,
+    /// `FolderIndex` sorted file names per folder, listed on first use.
+    folder_listings: RefCell<HashMap<PathBuf, Vec<OsString>>>,
+    /// `RegexSortSerial` / `TimeOrderSerial` rank (0-based) of each batch
+    /// index, see [`Self::for_batch`].
+    sort_ranks: HashMap<usize, usize>,
+    /// `AlignNumbers` width: digits in the widest first number of the batch.
+    align_width: usize,
+    /// `RebaseNumbers` offset: `new_start` minus the batch's smallest first
+    /// number.
+    rebase_delta: i64,
+    /// `Script` engine and its compiled source.
+    script: Option<Result<(rhai::Engine, rhai::AST), NameError>>,
+}
+
+impl<'a> PreparedCommand<'a> {
+    /// Like [`Self::new`], but also looks at the whole batch for commands that
+    /// number files by a batch-wide order.
+    pub(super) fn for_batch(cmd: &'a RenameCommand, paths: &[String]) -> Self {
+        Self::for_batch_at(cmd, paths, paths)
+    }
+
+    /// Like [`Self::for_batch`] for files that may have moved since: `current`
+    /// is where each of `paths` is now. File times are read there, while
+    /// names still come from `paths`.
+    pub(super) fn for_batch_at(
+        cmd: &'a RenameCommand,
+        paths: &[String],
+        current: &[String],
+    ) -> Self {
+        let mut prepared = Self::new(cmd);
+        if let (RenameCommand::RegexSortSerial { key_group, .. }, Some(Ok(re))) =
+            (cmd, &prepared.regex)
+        {
+            prepared.sort_ranks = sort_ranks(re, *key_group, paths);
+        }
+        if let RenameCommand::TimeOrderSerial { .. } = cmd {
+            prepared.sort_ranks = time_ranks(paths, current);
+        }
+        if let RenameCommand::AlignNumbers = cmd {
+            prepared.align_width = paths
+                .iter()
+                .filter_map(|path| {
+                    let stem = Path::new(path).file_stem()?.to_string_lossy();
+                    let chars: Vec<char> = stem.chars().collect();
+                    first_digit_run(&chars).map(|(start, end)| end - start)
+                })
+                .max()
+                .unwrap_or(0);
+        }
+        if let RenameCommand::RebaseNumbers { new_start } = cmd {
+            let min = paths
+                .iter()
+                .filter_map(|path| {
+                    let stem = Path::new(path).file_stem()?.to_string_lossy();
+                    let chars: Vec<char> = stem.chars().collect();
+                    let (start, end) = first_digit_run(&chars)?;
+                    chars[start..end].iter().collect::<String>().parse().ok()
+                })
+                .min()
+                .unwrap_or(u64::from(*new_start));
+            let delta = i128::from(*new_start) - i128::from(min);
+            prepared.rebase_delta = i64::try_from(delta).unwrap_or(i64::MIN);
+        }
+        prepared
+    }
+
+    pub(super) fn new(cmd: &'a RenameCommand) -> Self {
+        let regex = match cmd {
+            RenameCommand::Replace {
+                from,
+                use_regex: true,
+                ignore_case,
+                multi_line,
+                dot_matches_new_line,
+                ..
+            } => Some(
+                RegexBuilder::new(from)
+                    .case_insensitive(*ignore_case)
+                    .multi_line(*multi_line)
+                    .dot_matches_new_line(*dot_matches_new_line)
+                    .build()
+                    .map_err(|e| {
+                        NameError::new(ResultCode::InvalidInput, format!("Regex error: {}", e))
+                    }),
+            ),
+            RenameCommand::Replace {
+                from,
+                use_regex: false,
+                preserve_case: true,
+                ..
+            } => Some(
+                RegexBuilder::new(&regex::escape(from))
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| {
+                        NameError::new(ResultCode::InvalidInput, format!("Regex error: {}", e))
+                    }),
+            ),
+            RenameCommand::ReformatDate { from, .. } => Some(date_pattern_regex(from)),
+            RenameCommand::RegexSortSerial { pattern, .. }
+            | RenameCommand::SortInto { pattern, .. } => Some(Regex::new(pattern).map_err(|e| {
+                NameError::new(ResultCode::InvalidInput, format!("Regex error: {}", e))
+            })),
+            RenameCommand::RemoveWords {
+                words,
+                whole_word,
+                ignore_case,
+            } => Some(remove_words_regex(words, *whole_word, *ignore_case)),
+            RenameCommand::StripEmoji { .. } => Some(Regex::new(EMOJI_PATTERN).map_err(|e| {
+                NameError::new(ResultCode::InvalidInput, format!("Regex error: {}", e))
+            })),
+            _ => None,
+        };
+        let dictionary = match cmd {
+            RenameCommand::Abbreviations {
+                dictionary,
+                direction,
+            } => Some(load_dictionary(dictionary, *direction)),
+            _ => None,
+        };
+        let counter_start = match cmd {
+            RenameCommand::Counter { key, .. } => Some(peek_counter(key)),
+            _ => None,
+        };
+        let script = match cmd {
+            RenameCommand::Script { source } => Some(compile_script(source)),
+            _ => None,
+        };
+        PreparedCommand {
+            cmd,
+            regex,
+            dictionary,
+            counter_start: RefCell::new(counter_start),
+            stamps_seen: RefCell::new(HashMap::new()),
+            folder_listings: RefCell::new(HashMap::new()),
+            sort_ranks: HashMap::new(),
+            align_width: 0,
+            rebase_delta: 0,
+            script,
+        }
+    }
+
+    /// The compiled pattern for regex-based commands.
+    fn regex(&self) -> Result<&Regex, NameError> {
+        match &self.regex {
+            Some(Ok(re)) => Ok(re),
+            Some(Err(e)) => Err(e.clone()),
+            None => unreachable!("regex requested for a command without one"),
+        }
+    }
+
+    /// The compiled engine and AST for `Script`.
+    fn script(&self) -> Result<&(rhai::Engine, rhai::AST), NameError> {
+        match &self.script {
+            Some(Ok(script)) => Ok(script),
+            Some(Err(e)) => Err(e.clone()),
+            None => unreachable!("script requested for a command without one"),
+        }
+    }
+
+    /// The loaded dictionary for `Abbreviations`.
+    fn dictionary(&self) -> Result<&Dictionary, NameError> {
+        match &self.dictionary {
+            Some(Ok(dict)) => Ok(dict),
+            Some(Err(e)) => Err(e.clone()),
+            None => unreachable!("dictionary requested for a command without one"),
+        }
+    }
+
+    /// The number `Counter` assigns to the `index`-th file.
+    pub(super) fn counter_value(&self, index: usize) -> Result<u64, NameError> {
+        match &*self.counter_start.borrow() {
+            Some(Ok(start)) => Ok(start + index as u64),
+            Some(Err(e)) => Err(e.clone()),
+            None => unreachable!("counter requested for a command without one"),
+        }
+    }
+}
+
+/// Batch index → 0-based position after sorting the files by the `key_group`
+/// capture of their names. Keys that are all digits sort numerically and
+/// before the rest, which sort as text; ties keep batch order. Files whose
+/// name doesn't match get no rank.
+fn sort_ranks(re: &Regex, key_group: usize, paths: &[String]) -> HashMap<usize, usize> {
+    let mut keyed: Vec<(usize, Option<u128>, String)> = paths
+        .iter()
+        .enumerate()
+        .filter_map(|(i, path)| {
+            let name = Path::new(path).file_name()?.to_string_lossy();
+            let key = re.captures(&name)?.get(key_group)?.as_str().to_string();
+            Some((i, key.parse::<u128>().ok(), key))
+        })
+        .collect();
+    keyed.sort_by(|a, b| match (a.1, b.1) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.2.cmp(&b.2),
+    });
+    keyed
+        .iter()
+        .enumerate()
+        .map(|(rank, (i, _, _))| (*i, rank))
+        .collect()
+}
+
+/// Batch index → 0-based position after sorting the files by modification
+/// time, then file name. Times are read from `current`, the files' present
+/// locations. Files whose time can't be read sort last.
+fn time_ranks(paths: &[String], current: &[String]) -> HashMap<usize, usize> {
+    let mut keyed: Vec<(usize, Option<SystemTime>, OsString)> = paths
+        .iter()
+        .zip(current)
+        .enumerate()
+        .map(|(i, (path, current))| {
+            let path = Path::new(path);
+            let mtime = fs::metadata(current).and_then(|m| m.modified()).ok();
+            (
+                i,
+                mtime,
+                path.file_name().unwrap_or_default().to_os_string(),
+            )
+        })
+        .collect();
+    keyed.sort_by(|a, b| match (a.1, b.1) {
+        (Some(x), Some(y)) => x.cmp(&y).then_with(|| a.2.cmp(&b.2)),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.2.cmp(&b.2),
+    });
+    keyed
+        .iter()
+        .enumerate()
+        .map(|(rank, (i, _, _))| (*i, rank))
+        .collect()
+}
+
+/// Stable one-line description of `cmd` for test names and logs: the mode,
+/// then its config as JSON with keys in sorted order, e.g.
+/// `Trim {"count":2,"position":"start"}`. Equal commands always describe the
+/// same way.
+pub fn describe(cmd: &RenameCommand) -> String {
+    let value = serde_json::to_value(cmd).unwrap_or_default();
+    let mode = value["mode"].as_str().unwrap_or_default();
+    match value.get("config") {
+        Some(config) => format!("{} {}", mode, config),
+        None => mode.to_string(),
+    }
+}
+
+/// Compute the new file name for `cmd` without modifying anything on disk;
+/// only metadata-driven commands such as `ExifDate` read `old_path`.
+/// `name_stem` and `ext` are the two halves of `old_name`; `ext` may be empty.
+/// `index` is the file's position in its batch (0 for single renames).
+pub(super) fn compute_new_name(
+    old_path: &Path,
+    old_name: &str,
+    name_stem: &str,
+    ext: &str,
+    cmd: &PreparedCommand,
+    index: usize,
+) -> Result<String, NameError> {
+    match cmd.cmd {
+        // --- Fixed: replace entire name, optionally expanding `{date}`-style tokens ---
+        RenameCommand::Fixed {
+            name,
+            keep_ext,
+            expand_tokens: expand,
+        } => {
+            let name = if *expand {
+                expand_tokens(name)?
+            } else {
+                name.clone()
+            };
+            if *keep_ext && !ext.is_empty() {
+                Ok(join_name_ext(&name, ext))
+            } else {
+                Ok(name)
+            }
+        }
+
+        // --- Serial: prefix + (original?) + number + suffix ---
+        RenameCommand::Serial {
+            prefix,
+            suffix,
+            number,
+            pad,
+            keep_ext,
+            keep_original,
+            pad_side,
+            pad_char,
+            sign,
+            radix,
+        } => {
+            let num_str = pad_serial(
+                serial_number(*number, index),
+                *pad,
+                pad_side,
+                *pad_char,
+                *sign,
+                *radix,
+            );
+            let placed_ext = prefix.contains(EXT_TOKEN) || suffix.contains(EXT_TOKEN);
+            let prefix = prefix.replace(EXT_TOKEN, ext);
+            let suffix = suffix.replace(EXT_TOKEN, ext);
+            let generated = if *keep_original {
+                // prefix + original_stem + number + suffix
+                format!("{}{}{}{}", prefix, name_stem, num_str, suffix)
+            } else {
+                format!("{}{}{}", prefix, num_str, suffix)
+            };
+
+            // An explicitly placed `{ext}` wins over keep_ext, so it isn't doubled
+            if *keep_ext && !ext.is_empty() && !placed_ext {
+                Ok(join_name_ext(&generated, ext))
+            } else {
+                Ok(generated)
+            }
+        }
+
+        // --- Replace: string or regex replacement ---
+        RenameCommand::Replace {
+            from,
+            to,
+            use_regex,
+            preserve_ext,
+            literal_replacement,
+            preserve_case,
+            ..
+        } => {
+            // A pattern that never matches is almost always a mistake, so say
+            // so rather than reporting the untouched name as Unchanged
+            let no_match = || NameError::new(ResultCode::NoMatch, "No match");
+            let replaced = if *use_regex {
+                let re = cmd.regex()?;
+                if !re.is_match(old_name) {
+                    return Err(no_match());
+                }
+                if *literal_replacement {
+                    re.replace_all(old_name, regex::NoExpand(to)).to_string()
+                } else {
+                    re.replace_all(old_name, to.as_str()).to_string()
+                }
+            } else if *preserve_case {
+                let re = cmd.regex()?;
+                if !re.is_match(old_name) {
+                    return Err(no_match());
+                }
+                re.replace_all(old_name, |caps: &regex::Captures| match_case(&caps[0], to))
+                    .to_string()
+            } else {
+                if !old_name.contains(from.as_str()) {
+                    return Err(no_match());
+                }
+                old_name.replace(from, to)
+            };
+            if *preserve_ext && !ext.is_empty() {
+                // Same stem rule as `plan_rename`, applied to the new name
+                let new_stem = Path::new(&replaced)
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or(&replaced);
+                Ok(join_name_ext(new_stem, ext))
+            } else {
+                Ok(replaced)
+            }
+        }
+
+        // --- Add: prepend or append text to stem ---
+        RenameCommand::Add {
+            text,
+            position,
+            repad_trailing,
+        } => {
+            let (dot, stem) = split_leading_dot(name_stem);
+            let repadded;
+            let stem = match repad_trailing {
+                Some(width) => {
+                    repadded = repad_trailing_number(stem, *width);
+                    &repadded
+                }
+                None => stem,
+            };
+            let new_stem = match position {
+                Position::Start => format!("{}{}{}", dot, text, stem),
+                Position::End => format!("{}{}{}", dot, stem, text),
+            };
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- Wrap: surround the stem with `open` and `close` ---
+        RenameCommand::Wrap { open, close } => {
+            check_wrapper(open, close)?;
+            let (dot, stem) = split_leading_dot(name_stem);
+            Ok(join_name_ext(
+                &format!("{}{}{}{}", dot, open, stem, close),
+                ext,
+            ))
+        }
+
+        // --- Trim / TrimBoth: remove characters from one or both stem ends ---
+        RenameCommand::Trim { .. } | RenameCommand::TrimBoth { .. } => {
+            let (start, end) = match cmd.cmd {
+                RenameCommand::Trim {
+                    count,
+                    position: Position::Start,
+                } => (*count, 0),
+                RenameCommand::Trim { count, .. } => (0, *count),
+                RenameCommand::TrimBoth { start, end } => (*start, *end),
+                _ => unreachable!(),
+            };
+            let (dot, stem) = split_leading_dot(name_stem);
+            let trimmed = trim_stem(stem, start, end)?;
+            Ok(join_name_ext(&format!("{}{}", dot, trimmed), ext))
+        }
+
+        // --- TrimWhitespace: strip whitespace (incl. U+3000) from stem edges ---
+        RenameCommand::TrimWhitespace { start, end } => {
+            let mut new_stem = name_stem;
+            if *start {
+                new_stem = new_stem.trim_start();
+            }
+            if *end {
+                new_stem = new_stem.trim_end();
+            }
+            Ok(join_name_ext(new_stem, ext))
+        }
+
+        // --- SeparateAlnum: split letter/digit runs (stem only) ---
+        RenameCommand::SeparateAlnum { separator } => {
+            let (dot, stem) = split_leading_dot(name_stem);
+            Ok(join_name_ext(
+                &format!("{}{}", dot, separate_alnum(stem, separator)),
+                ext,
+            ))
+        }
+
+        // --- TrimSeparators: listed characters off the stem's edges ---
+        RenameCommand::TrimSeparators { chars, start, end } => {
+            let (dot, mut stem) = split_leading_dot(name_stem);
+            if *start {
+                stem = stem.trim_start_matches(chars.as_slice());
+            }
+            if *end {
+                stem = stem.trim_end_matches(chars.as_slice());
+            }
+            Ok(join_name_ext(&format!("{}{}", dot, stem), ext))
+        }
+
+        // --- TrimRelative: keep the stem on one side of the first marker ---
+        RenameCommand::TrimRelative {
+            marker,
+            keep,
+            include_marker,
+        } => {
+            if marker.is_empty() {
+                return Err(NameError::new(ResultCode::InvalidInput, "Marker is empty"));
+            }
+            let Some(at) = name_stem.find(marker.as_str()) else {
+                return Err(NameError::new(ResultCode::NoMatch, "Marker not found"));
+            };
+            let end = at + marker.len();
+            let kept = match (keep, include_marker) {
+                (MarkerSide::Before, false) => &name_stem[..at],
+                (MarkerSide::Before, true) => &name_stem[..end],
+                (MarkerSide::After, false) => &name_stem[end..],
+                (MarkerSide::After, true) => &name_stem[at..],
+            };
+            Ok(join_name_ext(kept, ext))
+        }
+
+        // --- Extension: change file extension ---
+        RenameCommand::Extension { new_ext } => {
+            let clean_ext = new_ext.trim_start_matches('.');
+            Ok(format!("{}.{}", name_stem, clean_ext))
+        }
+
+        // --- SwapExtension: change only extensions equal to `from` (any case) ---
+        RenameCommand::SwapExtension { from, to } => {
+            // Anything else keeps its name and is reported as Unchanged
+            if !ext.eq_ignore_ascii_case(from.trim_start_matches('.')) || ext.is_empty() {
+                return Ok(old_name.to_string());
+            }
+            Ok(join_name_ext(name_stem, to.trim_start_matches('.')))
+        }
+
+        // --- ExtCaseFor: recase only the listed extensions ---
+        RenameCommand::ExtCaseFor { extensions, mode } => {
+            let listed = extensions
+                .iter()
+                .any(|e| ext.eq_ignore_ascii_case(e.trim_start_matches('.')));
+            if ext.is_empty() || !listed {
+                return Ok(old_name.to_string());
+            }
+            let new_ext = match mode {
+                CaseMode::Upper => ext.to_uppercase(),
+                CaseMode::Lower => ext.to_lowercase(),
+            };
+            Ok(join_name_ext(name_stem, &new_ext))
+        }
+
+        // --- EnsureExt: append extension unless already present (idempotent) ---
+        RenameCommand::EnsureExt { ext: wanted } => {
+            let clean_ext = wanted.trim_start_matches('.');
+            if clean_ext.is_empty() {
+                Err(NameError::new(
+                    ResultCode::InvalidInput,
+                    "Extension is empty",
+                ))
+            } else if ext.eq_ignore_ascii_case(clean_ext) {
+                Ok(old_name.to_string())
+            } else {
+                Ok(format!("{}.{}", old_name, clean_ext))
+            }
+        }
+
+        // --- DedupeExtension: `x.pdf.pdf` -> `x.pdf`, keeping the real one ---
+        RenameCommand::DedupeExtension => {
+            if ext.is_empty() {
+                return Ok(old_name.to_string());
+            }
+            let mut stem = name_stem;
+            while let Some((rest, inner)) = stem.rsplit_once('.') {
+                // `.pdf.pdf`: the first `.pdf` is a hidden file's whole stem
+                if rest.is_empty() || !inner.eq_ignore_ascii_case(ext) {
+                    break;
+                }
+                stem = rest;
+            }
+            Ok(join_name_ext(stem, ext))
+        }
+
+        // --- Case: upper/lower conversion (stem; extension with include_ext) ---
+        RenameCommand::Case {
+            mode,
+            split,
+            only_if,
+            include_ext,
+        } => {
+            let (name_stem, ext) = split_ext(old_name, name_stem, ext, *split);
+            let (dot, stem) = split_leading_dot(name_stem);
+            if !only_if.holds(stem) {
+                return Ok(old_name.to_string());
+            }
+            let recase = |s: &str| match mode {
+                CaseMode::Upper => s.to_uppercase(),
+                CaseMode::Lower => s.to_lowercase(),
+            };
+            let new_ext = if *include_ext {
+                recase(ext)
+            } else {
+                ext.to_string()
+            };
+            Ok(join_name_ext(&format!("{}{}", dot, recase(stem)), &new_ext))
+        }
+
+        // --- CapitalizeAfter: uppercase after chosen delimiters (stem only) ---
+        RenameCommand::CapitalizeAfter { delimiters } => {
+            Ok(join_name_ext(&capitalize_after(name_stem, delimiters), ext))
+        }
+
+        // --- Convert: zenkaku/hankaku conversion (stem only, preserve extension) ---
+        RenameCommand::Convert { mode, split } => {
+            let (name_stem, ext) = split_ext(old_name, name_stem, ext, *split);
+            // Keep the dot ASCII so a dotfile stays hidden
+            let (dot, stem) = split_leading_dot(name_stem);
+            let new_stem = match mode {
+                WidthMode::Zenkaku => to_zenkaku(stem),
+                WidthMode::Hankaku => to_hankaku(stem),
+            };
+            Ok(join_name_ext(&format!("{}{}", dot, new_stem), ext))
+        }
+
+        // --- JaPunctuation: 、。「」・ to/from half-width (stem only) ---
+        RenameCommand::JaPunctuation { to } => {
+            Ok(join_name_ext(&convert_ja_punctuation(name_stem, to), ext))
+        }
+
+        // --- HanConvert: simplified <-> traditional Chinese (stem only) ---
+        RenameCommand::HanConvert { to } => {
+            let variant = match to {
+                HanScript::Simplified => zhconv::Variant::ZhHans,
+                HanScript::Traditional => zhconv::Variant::ZhHant,
+            };
+            Ok(join_name_ext(&zhconv::zhconv(name_stem, variant), ext))
+        }
+
+        // --- Compatibility: NFKC/NFKD folding (stem only, preserve extension) ---
+        // Broader than Convert: also folds circled numbers, ligatures, etc.
+        // Composes like NFC too, so conjoining Hangul jamo become syllables
+        RenameCommand::Compatibility { form } => {
+            let new_stem: String = match form {
+                CompatibilityForm::Nfkc => name_stem.nfkc().collect(),
+                CompatibilityForm::Nfkd => name_stem.nfkd().collect(),
+            };
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- GroupDigits: add/remove thousands separators in digit runs (stem only) ---
+        RenameCommand::GroupDigits { separator, strip } => Ok(join_name_ext(
+            &group_digits(name_stem, separator, strip),
+            ext,
+        )),
+
+        // --- MoveNumber: relocate the first number to the start/end (stem only) ---
+        RenameCommand::MoveNumber { to } => match move_number(name_stem, to) {
+            Some(new_stem) => Ok(join_name_ext(&new_stem, ext)),
+            None => Err(NameError::new(ResultCode::NoNumberFound, "No number found")),
+        },
+
+        // --- CollapseRepeats: squeeze runs of listed characters (stem only) ---
+        RenameCommand::CollapseRepeats { chars } => {
+            Ok(join_name_ext(&collapse_repeats(name_stem, chars), ext))
+        }
+
+        // --- StripControlChars: replace ASCII control chars (stem only) ---
+        RenameCommand::StripControlChars { replacement } => {
+            let new_stem: String = name_stem
+                .split(|c: char| c.is_ascii_control())
+                .collect::<Vec<_>>()
+                .join(replacement);
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- RemoveWords: drop listed words and tidy the gaps (stem only) ---
+        RenameCommand::RemoveWords { whole_word, .. } => {
+            let (dot, stem) = split_leading_dot(name_stem);
+            let new_stem = remove_words(stem, cmd.regex()?, *whole_word);
+            Ok(join_name_ext(&format!("{}{}", dot, new_stem), ext))
+        }
+
+        // --- StripBidi: drop direction marks and overrides (stem only) ---
+        RenameCommand::StripBidi => {
+            let new_stem: String = name_stem.chars().filter(|&c| !is_bidi_control(c)).collect();
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- FolderIndex: 1-based sorted position among the folder's files ---
+        // Stable regardless of selection or processing order, unlike Serial.
+        RenameCommand::FolderIndex { pad, position } => {
+            let n = folder_index(old_path, &cmd.folder_listings)
+                .map_err(|e| NameError::new(ResultCode::IoError, e.to_string()))?;
+            let num_str = format!("{:0width$}", n, width = pad);
+            let (dot, stem) = split_leading_dot(name_stem);
+            let new_stem = match position {
+                Position::Start => format!("{}{}_{}", dot, num_str, stem),
+                Position::End => format!("{}{}_{}", dot, stem, num_str),
+            };
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- Abbreviations: whole-word dictionary replacement (stem only) ---
+        RenameCommand::Abbreviations { .. } => Ok(join_name_ext(
+            &apply_dictionary(name_stem, cmd.dictionary()?),
+            ext,
+        )),
+
+        // --- ShiftNumber: offset the first number, never below 0 (stem only) ---
+        RenameCommand::ShiftNumber { delta, pad } => match shift_number(name_stem, *delta, *pad) {
+            Some(new_stem) => Ok(join_name_ext(&new_stem?, ext)),
+            None => Err(NameError::new(ResultCode::NoNumberFound, "No number found")),
+        },
+
+        // --- FlattenName: prefix the stem with its folders below `root` ---
+        RenameCommand::FlattenName { root, separator } => {
+            let relative = old_path
+                .strip_prefix(root)
+                .map_err(|_| NameError::new(ResultCode::InvalidInput, "Path is not under root"))?;
+            let mut parts = Vec::new();
+            for dir in relative.parent().into_iter().flat_map(Path::components) {
+                parts.push(dir.as_os_str().to_str().ok_or_else(|| {
+                    NameError::new(ResultCode::InvalidFilename, "Invalid folder name")
+                })?);
+            }
+            parts.push(name_stem);
+            Ok(join_name_ext(&parts.join(separator), ext))
+        }
+
+        // --- AlignNumbers: pad the first number to the batch's widest ---
+        RenameCommand::AlignNumbers => match pad_first_number(name_stem, cmd.align_width) {
+            Some(new_stem) => Ok(join_name_ext(&new_stem, ext)),
+            None => Err(NameError::new(ResultCode::NoNumberFound, "No number found")),
+        },
+
+        // --- RebaseNumbers: shift the first number by the batch's offset ---
+        RenameCommand::RebaseNumbers { .. } => {
+            let chars: Vec<char> = name_stem.chars().collect();
+            let width = first_digit_run(&chars).map_or(0, |(start, end)| end - start);
+            match shift_number(name_stem, cmd.rebase_delta, width) {
+                Some(new_stem) => Ok(join_name_ext(&new_stem?, ext)),
+                None => Err(NameError::new(ResultCode::NoNumberFound, "No number found")),
+            }
+        }
+
+        // --- Counter: like Serial, numbered from a counter kept across sessions ---
+        RenameCommand::Counter {
+            prefix,
+            suffix,
+            pad,
+            keep_ext,
+            ..
+        } => {
+            let n = cmd.counter_value(index)?;
+            let generated = format!("{}{:0width$}{}", prefix, n, suffix, width = pad);
+            if *keep_ext && !ext.is_empty() {
+                Ok(join_name_ext(&generated, ext))
+            } else {
+                Ok(generated)
+            }
+        }
+
+        // --- TimeStamp: name from mtime or the current time, optionally de-duplicated ---
+        RenameCommand::TimeStamp {
+            format,
+            use_file_time,
+            ensure_unique,
+        } => {
+            let time = if *use_file_time {
+                read_mtime(old_path).ok_or_else(|| {
+                    NameError::new(ResultCode::IoError, "Could not read modification time")
+                })?
+            } else {
+                Local::now().naive_local()
+            };
+            let stamp = format_datetime(&time, format)?;
+            if !*ensure_unique {
+                return Ok(join_name_ext(&stamp, ext));
+            }
+
+            // Bursts share a formatted time: number repeats `_1`, `_2`, ...
+            // skipping names already taken on disk by other files
+            let mut seen = cmd.stamps_seen.borrow_mut();
+            let count = seen.entry(stamp.clone()).or_insert(0);
+            loop {
+                let candidate = match *count {
+                    0 => join_name_ext(&stamp, ext),
+                    n => join_name_ext(&format!("{}_{}", stamp, n), ext),
+                };
+                *count += 1;
+                let taken = candidate != old_name && old_path.with_file_name(&candidate).exists();
+                if !taken {
+                    return Ok(candidate);
+                }
+            }
+        }
+
+        // --- ReformatDate: rewrite the first date in `from` format as `to` (stem only) ---
+        RenameCommand::ReformatDate { from, to } => {
+            let found = cmd
+                .regex()?
+                .find_iter(name_stem)
+                .find_map(|m| parse_date_with(m.as_str(), from).map(|date| (m.range(), date)));
+            let Some((range, date)) = found else {
+                return Err(NameError::new(ResultCode::NoDateMatched, "No date matched"));
+            };
+
+            let new_stem = format!(
+                "{}{}{}",
+                &name_stem[..range.start],
+                format_datetime(&date, to)?,
+                &name_stem[range.end..]
+            );
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- StripEmoji: remove or replace whole emoji sequences (stem only) ---
+        RenameCommand::StripEmoji { replacement } => {
+            let new_stem = cmd
+                .regex()?
+                .replace_all(name_stem, regex::NoExpand(replacement));
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- Slugify: URL-safe lowercase ASCII stem (preserve extension) ---
+        RenameCommand::Slugify {
+            separator,
+            max_length,
+        } => Ok(join_name_ext(
+            &slugify(name_stem, separator, *max_length),
+            ext,
+        )),
+
+        // --- RegexSortSerial: number by the sorted order of a captured key ---
+        // Ranks come from the whole batch; a single rename is always number 1.
+        RenameCommand::RegexSortSerial {
+            key_group,
+            prefix,
+            pad,
+            ..
+        } => {
+            let matched = cmd
+                .regex()?
+                .captures(old_name)
+                .is_some_and(|caps| caps.get(*key_group).is_some());
+            if !matched {
+                return Err(NameError::new(ResultCode::NoMatch, "No match"));
+            }
+            let rank = cmd.sort_ranks.get(&index).copied().unwrap_or(0);
+            let generated = format!("{}{:0width$}", prefix, rank + 1, width = pad);
+            Ok(join_name_ext(&generated, ext))
+        }
+
+        // --- Script: whatever the user's Rhai script returns ---
+        RenameCommand::Script { .. } => {
+            let (engine, ast) = cmd.script()?;
+            run_script(engine, ast, old_path, old_name, name_stem, ext, index)
+        }
+
+        // --- MatchSibling: take the stem of the paired reference file ---
+        RenameCommand::MatchSibling { reference_ext } => {
+            let reference_ext = reference_ext.trim_start_matches('.');
+            if reference_ext.is_empty() {
+                return Err(NameError::new(
+                    ResultCode::InvalidInput,
+                    "Extension is empty",
+                ));
+            }
+            let new_stem = sibling_stem(old_path, name_stem, reference_ext)?;
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- SortInto: the name stays, plan_rename picks the subfolder ---
+        RenameCommand::SortInto { .. } => {
+            if !cmd.regex()?.is_match(old_name) {
+                return Err(NameError::new(ResultCode::NoMatch, "No match"));
+            }
+            Ok(old_name.to_string())
+        }
+
+        // --- TimeOrderSerial: number by modification time across the batch ---
+        RenameCommand::TimeOrderSerial {
+            prefix,
+            pad,
+            keep_ext,
+        } => {
+            let rank = cmd.sort_ranks.get(&index).copied().unwrap_or(0);
+            let generated = format!("{}{:0width$}", prefix, rank + 1, width = pad);
+            Ok(if *keep_ext && !ext.is_empty() {
+                join_name_ext(&generated, ext)
+            } else {
+                generated
+            })
+        }
+
+        // --- AddChecksum: append a content digest to the stem ---
+        RenameCommand::AddChecksum {
+            algo,
+            length,
+            max_bytes,
+            timeout_ms,
+        } => {
+            if *length == 0 {
+                return Err(NameError::new(
+                    ResultCode::InvalidInput,
+                    "Checksum length must be at least 1",
+                ));
+            }
+            let digest = file_checksum_limited(
+                old_path,
+                *algo,
+                *max_bytes,
+                timeout_ms.map(Duration::from_millis),
+            )?;
+            // Longer than the digest just means "all of it"
+            let short = &digest[..(*length).min(digest.len())];
+            Ok(join_name_ext(&format!("{}_{}", name_stem, short), ext))
+        }
+
+        // --- ExifDate: name from capture time, optionally falling back to mtime ---
+        RenameCommand::ExifDate {
+            format,
+            keep_ext,
+            fallback_to_mtime,
+        } => {
+            let taken = read_exif_date(old_path).or_else(|| {
+                if *fallback_to_mtime {
+                    read_mtime(old_path)
+                } else {
+                    None
+                }
+            });
+            let Some(taken) = taken else {
+                return Err(NameError::new(ResultCode::NoExifDate, "No EXIF date"));
+            };
+
+            let stamp = format_datetime(&taken, format)?;
+            if *keep_ext && !ext.is_empty() {
+                Ok(join_name_ext(&stamp, ext))
+            } else {
+                Ok(stamp)
+            }
+        }
+
+        // --- AddDimensions: insert the image's WxH (stem only) ---
+        RenameCommand::AddDimensions {
+            separator,
+            position,
+        } => {
+            let Some((width, height)) = read_dimensions(old_path) else {
+                return Err(NameError::new(ResultCode::NotAnImage, "Not an image"));
+            };
+            let size = format!("{}x{}", width, height);
+            let (dot, stem) = split_leading_dot(name_stem);
+            let new_stem = match position {
+                Position::Start => format!("{}{}{}{}", dot, size, separator, stem),
+                Position::End => format!("{}{}{}{}", dot, stem, separator, size),
+            };
+            Ok(join_name_ext(&new_stem, ext))
+        }
+
+        // --- MediaTags: name from artist/album/title/track/year tags ---
+        RenameCommand::MediaTags {
+            pattern,
+            placeholder,
+        } => {
+            let Some(tags) = read_media_tags(old_path) else {
+                return Err(NameError::new(ResultCode::NoTags, "No tags"));
+            };
+            let stem = expand_braces(pattern, |token| match token {
+                "artist" | "album" | "title" | "track" | "year" => Ok(tags
+                    .get(token)
+                    .map(|value| sanitize_name_part(value))
+                    .unwrap_or_else(|| placeholder.clone())),
+                _ => Err(NameError::new(
+                    ResultCode::InvalidInput,
+                    format!("Unknown token: {{{}}}", token),
+                )),
+            })?;
+            Ok(join_name_ext(&stem, ext))
+        }
+    }
+}
+
+/// Placeholder in `Serial`'s prefix/suffix for the original extension
+/// (without the dot).
+const EXT_TOKEN: &str = "{ext}";
+
+/// `template` with every [`EXT_TOKEN`] replaced by the raw extension bytes.
+fn replace_ext_token_os(template: &str, ext: &OsStr) -> OsString {
+    let mut out = OsString::new();
+    for (i, part) in template.split(EXT_TOKEN).enumerate() {
+        if i > 0 {
+            out.push(ext);
+        }
+        out.push(part);
+    }
+    out
+}
+
+/// Serial number for the `index`-th file of a batch starting at `number`.
+fn serial_number(number: i64, index: usize) -> i64 {
+    number.saturating_add(index as i64)
+}
+
+/// `n`'s magnitude in `radix`, padded with `pad_char` to at least `width`
+/// chars on `side`, behind the sign `sign` asks for.
+pub(super) fn pad_serial(
+    n: i64,
+    width: usize,
+    side: &PadSide,
+    pad_char: char,
+    sign: SignMode,
+    radix: Radix,
+) -> String {
+    let digits = radix.digits(n.unsigned_abs());
+    let fill: String = std::iter::repeat_n(pad_char, width.saturating_sub(digits.len())).collect();
+    let sign = match (n.signum(), sign) {
+        (-1, _) => "-",
+        (1, SignMode::Always | SignMode::AlwaysWithZero) | (0, SignMode::AlwaysWithZero) => "+",
+        _ => "",
+    };
+    match side {
+        PadSide::Left => format!("{}{}{}", sign, fill, digits),
+        PadSide::Right => format!("{}{}{}", sign, digits, fill),
+    }
+}
+
+/// Batch check for a `Serial` whose number is the whole differentiator
+/// (`keep_original: false`): every number in the batch must fit in `pad`
+/// and pad to a distinct string. Right padding with `'0'` can repeat, since
+/// `1` and `10` both become `100` at width 3.
+pub(super) fn check_serial_batch(cmd: &RenameCommand, count: usize) -> Result<(), NameError> {
+    let RenameCommand::Serial {
+        number,
+        pad,
+        keep_original: false,
+        pad_side,
+        pad_char,
+        sign,
+        radix,
+        ..
+    } = cmd
+    else {
+        return Ok(());
+    };
+    if count == 0 {
+        return Ok(());
+    }
+    // The widest magnitude is at one end of the range
+    let last = serial_number(*number, count - 1);
+    let widest = if number.unsigned_abs() > last.unsigned_abs() {
+        *number
+    } else {
+        last
+    };
+    if *pad > 0 && radix.digits(widest.unsigned_abs()).len() > *pad {
+        return Err(NameError::new(
+            ResultCode::InvalidInput,
+            format!(
+                "Serial pad {} is too narrow for numbers up to {}",
+                pad, widest
+            ),
+        ));
+    }
+    let mut seen = HashSet::new();
+    for index in 0..count {
+        let padded = pad_serial(
+            serial_number(*number, index),
+            *pad,
+            pad_side,
+            *pad_char,
+            *sign,
+            *radix,
+        );
+        if !seen.insert(padded.clone()) {
+            return Err(NameError::new(
+                ResultCode::InvalidInput,
+                format!("Serial numbers repeat: {}", padded),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Concatenate OS string fragments without any UTF-8 round trip.
+pub(super) fn concat_os(parts: &[&OsStr]) -> OsString {
+    let mut out = OsString::new();
+    for part in parts {
+        out.push(part);
+    }
+    out
+}
+
+/// Byte-level fallback of [`compute_new_name`] for names that aren't valid
+/// UTF-8 (legacy encodings on Linux). Only structural commands work here:
+/// they splice user text around the original bytes without inspecting them.
+fn compute_new_name_os(
+    old_name: &OsStr,
+    name_stem: &OsStr,
+    ext: Option<&OsStr>,
+    cmd: &RenameCommand,
+    index: usize,
+) -> Result<OsString, NameError> {
+    let dot = OsStr::new(".");
+    let with_ext = |stem: OsString| match ext {
+        Some(ext) => concat_os(&[&stem, dot, ext]),
+        None => stem,
+    };
+
+    match cmd {
+        RenameCommand::Fixed {
+            name,
+            keep_ext,
+            expand_tokens: expand,
+        } => {
+            let name = OsString::from(if *expand {
+                expand_tokens(name)?
+            } else {
+                name.clone()
+            });
+            Ok(if *keep_ext { with_ext(name) } else { name })
+        }
+        RenameCommand::Serial {
+            prefix,
+            suffix,
+            number,
+            pad,
+            keep_ext,
+            keep_original,
+            pad_side,
+            pad_char,
+            sign,
+            radix,
+        } => {
+            let num_str = pad_serial(
+                serial_number(*number, index),
+                *pad,
+                pad_side,
+                *pad_char,
+                *sign,
+                *radix,
+            );
+            let original = if *keep_original {
+                name_stem
+            } else {
+                OsStr::new("")
+            };
+            let placed_ext = prefix.contains(EXT_TOKEN) || suffix.contains(EXT_TOKEN);
+            let raw_ext = ext.unwrap_or_default();
+            let generated = concat_os(&[
+                &replace_ext_token_os(prefix, raw_ext),
+                original,
+                OsStr::new(&num_str),
+                &replace_ext_token_os(suffix, raw_ext),
+            ]);
+            Ok(if *keep_ext && !placed_ext {
+                with_ext(generated)
+            } else {
+                generated
+            })
+        }
+        RenameCommand::Add {
+            text,
+            position,
+            repad_trailing: None,
+        } => {
+            let text = OsStr::new(text);
+            Ok(with_ext(match position {
+                Position::Start => concat_os(&[text, name_stem]),
+                Position::End => concat_os(&[name_stem, text]),
+            }))
+        }
+        RenameCommand::Wrap { open, close } => {
+            check_wrapper(open, close)?;
+            Ok(with_ext(concat_os(&[
+                OsStr::new(open),
+                name_stem,
+                OsStr::new(close),
+            ])))
+        }
+        RenameCommand::Extension { new_ext } => {
+            let clean_ext = OsStr::new(new_ext.trim_start_matches('.'));
+            Ok(concat_os(&[name_stem, dot, clean_ext]))
+        }
+        RenameCommand::EnsureExt { ext: wanted } => {
+            let clean_ext = wanted.trim_start_matches('.');
+            let has_ext = ext
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| e.eq_ignore_ascii_case(clean_ext));
+            if clean_ext.is_empty() {
+                Err(NameError::new(
+                    ResultCode::InvalidInput,
+                    "Extension is empty",
+                ))
+            } else if has_ext {
+                Ok(old_name.to_os_string())
+            } else {
+                Ok(concat_os(&[old_name, dot, OsStr::new(clean_ext)]))
+            }
+        }
+        _ => Err(NameError::new(
+            ResultCode::InvalidFilename,
+            "Command not supported for non-UTF-8 filename",
+        )),
+    }
+}
+
+/// Build a path from raw OS bytes. Only Unix can express arbitrary bytes;
+/// elsewhere the bytes must be UTF-8.
+pub(super) fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Some(PathBuf::from(OsString::from_vec(bytes)))
+    }
+    #[cfg(not(unix))]
+    {
+        String::from_utf8(bytes).ok().map(PathBuf::from)
+    }
+}
+
+/// A file's computed new name, not yet validated or applied.
+pub(super) struct PlannedRename<'a> {
+    pub(super) old_path: &'a Path,
+    pub(super) parent: &'a Path,
+    pub(super) old_name: &'a OsStr,
+    pub(super) new_name: OsString,
+    /// `SortInto` folder, relative to `parent`.
+    subdir: Option<PathBuf>,
+}
+
+impl PlannedRename<'_> {
+    /// Directory the file will end up in.
+    pub(super) fn target_dir(&self) -> PathBuf {
+        match &self.subdir {
+            Some(subdir) => self.parent.join(subdir),
+            None => self.parent.to_path_buf(),
+        }
+    }
+
+    fn is_unchanged(&self) -> bool {
+        self.new_name == self.old_name && self.subdir.is_none()
+    }
+
+    /// Where the file will end up, or `None` if it stays where it is.
+    pub(super) fn target(&self) -> Option<PathBuf> {
+        (!self.is_unchanged()).then(|| self.target_dir().join(&self.new_name))
+    }
+
+    /// The new name as reported to the frontend, prefixed by the subfolder.
+    pub(super) fn display_name(&self) -> String {
+        display_target(self.subdir.as_deref(), &self.new_name)
+    }
+}
+
+fn display_target(subdir: Option<&Path>, name: &OsStr) -> String {
+    match subdir {
+        Some(subdir) => subdir.join(name).to_string_lossy().into_owned(),
+        None => name.to_string_lossy().into_owned(),
+    }
+}
+
+/// The `SortInto` subfolder for `name`: `folder` with the captures of the
+/// first match of `re` filled in. Every `/`-separated part must be a valid
+/// name, so the file can't leave its directory.
+fn sort_folder(re: &Regex, folder: &str, name: &str) -> Result<PathBuf, NameError> {
+    let caps = re
+        .captures(name)
+        .ok_or_else(|| NameError::new(ResultCode::NoMatch, "No match"))?;
+    let mut expanded = String::new();
+    caps.expand(folder, &mut expanded);
+
+    let mut subdir = PathBuf::new();
+    for part in expanded.split('/') {
+        if part.is_empty() || !check_name(part, cfg!(windows)).valid {
+            return Err(NameError::new(
+                ResultCode::InvalidName,
+                format!("Invalid folder: {:?}", expanded),
+            ));
+        }
+        subdir.push(part);
+    }
+    Ok(subdir)
+}
+
+/// Everything that can stop a planned rename short of the rename itself:
+/// name validation, no-op detection, safe mode and an occupied target.
+/// Returns the target path when the rename may go ahead.
+fn check_plan(plan: &PlannedRename, options: &PreparedOptions) -> Result<PathBuf, NameError> {
+    check_plan_with(plan, options, |path| path.exists())
+}
+
+/// [`check_plan`] against `exists` instead of the disk as it is now.
+pub(super) fn check_plan_with(
+    plan: &PlannedRename,
+    options: &PreparedOptions,
+    exists: impl Fn(&Path) -> bool,
+) -> Result<PathBuf, NameError> {
+    let display_name = plan.new_name.to_string_lossy();
+
+    let validation = check_name(&display_name, cfg!(windows));
+    if let Some(message) = validation.message() {
+        let code = if validation.empty {
+            ResultCode::EmptyName
+        } else {
+            ResultCode::InvalidName
+        };
+        return Err(NameError::new(code, message));
+    }
+
+    if plan.is_unchanged() {
+        return Err(NameError::new(ResultCode::Unchanged, "Unchanged"));
+    }
+
+    if options.options.safe_mode {
+        if let Some(reason) = safe_mode_violation(&plan.old_name.to_string_lossy(), &display_name) {
+            return Err(NameError::new(
+                ResultCode::BlockedBySafeMode,
+                format!("Blocked by safe mode: {}", reason),
+            ));
+        }
+    }
+
+    let target_dir = plan.target_dir();
+    let new_path = target_dir.join(&plan.new_name);
+
+    // Prevent overwriting existing files
+    if exists(&new_path) {
+        if let Collision::Suffix { template } = &options.options.collision {
+            let free = (1..=MAX_SUFFIX_ATTEMPTS)
+                .map(|n| target_dir.join(suffixed_name(&plan.new_name, template, n)))
+                .find(|candidate| !exists(candidate));
+            if let Some(free) = free {
+                return Ok(free);
+            }
+        }
+        return Err(NameError::new(
+            ResultCode::TargetExists,
+            format!("Target exists: {}", display_name),
+        ));
+    }
+
+    Ok(new_path)
+}
+
+/// Validate the planned name and rename the file to it within its parent directory.
+pub(super) fn execute_rename(
+    path: String,
+    plan: PlannedRename,
+    options: &PreparedOptions,
+) -> RenameResult {
+    let new_path = match check_plan(&plan, options) {
+        Ok(p) => p,
+        Err(e) => return RenameResult::from_error(path, e),
+    };
+    let PlannedRename {
+        old_path,
+        old_name,
+        new_name,
+        subdir,
+        ..
+    } = plan;
+    // Differs from the plan when `Collision::Suffix` picked another name
+    let new_name = new_path
+        .file_name()
+        .map(OsStr::to_os_string)
+        .unwrap_or(new_name);
+    let display_name = display_target(subdir.as_deref(), &new_name);
+
+    if subdir.is_some() {
+        if let Some(target_dir) = new_path.parent() {
+            if let Err(e) = fs::create_dir_all(target_dir) {
+                return RenameResult::failure(path, ResultCode::IoError, e.to_string());
+            }
+        }
+    }
+
+    match rename_noreplace(old_path, &new_path) {
+        Ok(_) => {
+            let diff = NameDiff::between(&old_name.to_string_lossy(), &display_name);
+            let mut res = RenameResult::success(path, display_name, diff);
+            if new_name.to_str().is_none() {
+                res.new_name_bytes = Some(new_name.as_encoded_bytes().to_vec());
+            }
+            record_rename(&mut res, old_path, &new_path, options, sync_dir);
+            res
+        }
+        // Target appeared after the exists() check above
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => RenameResult::failure(
+            path,
+            ResultCode::TargetExists,
+            format!("Target exists: {}", display_name),
+        ),
+        Err(e) if options.options.skip_locked && is_lock_error(&e) => {
+            RenameResult::failure(path, ResultCode::Locked, "Locked")
+        }
+        Err(e) => RenameResult::failure(path, ResultCode::IoError, e.to_string()),
+    }
+}
+
+/// The bookkeeping after `old_path` was renamed to `new_path`: flush the
+/// directories with `sync` (for `durable`), journal and write the sidecar.
+/// Each step that fails adds a warning to `res`; none undoes the rename.
+pub(super) fn record_rename(
+    res: &mut RenameResult,
+    old_path: &Path,
+    new_path: &Path,
+    options: &PreparedOptions,
+    sync: impl Fn(&Path) -> io::Result<()>,
+) {
+    if options.options.durable {
+        // A move into a `SortInto` folder changes two directories
+        let dirs = [old_path.parent(), new_path.parent()];
+        let mut dirs: Vec<&Path> = dirs.into_iter().flatten().collect();
+        dirs.dedup();
+        if let Err(e) = dirs.into_iter().try_for_each(sync) {
+            res.add_warning(format!("Sync failed: {}", e));
+        }
+    }
+    if let Some(journal_path) = &options.options.journal_path {
+        let entry = JournalEntry {
+            old: old_path.to_string_lossy().into_owned(),
+            new: new_path.to_string_lossy().into_owned(),
+            batch: Some(options.batch_id.clone()),
+            op: Some(format!(
+                "{}/{}",
+                options.batch_id,
+                options.ops_written.get()
+            )),
+        };
+        match append_journal(Path::new(journal_path), &entry) {
+            Ok(()) => {
+                options.ops_written.set(options.ops_written.get() + 1);
+                res.batch_id = entry.batch;
+                res.op_id = entry.op;
+            }
+            Err(e) => res.add_warning(format!("Journal write failed: {}", e)),
+        }
+    }
+    if options.options.write_sidecar {
+        if let Err(e) = write_sidecar(old_path, new_path) {
+            res.add_warning(format!("Sidecar write failed: {}", e));
+        }
+    }
+}
+
+/// Metadata the options ask to report, read before renaming so it describes
+/// the file that was targeted.
+#[derive(Default)]
+pub(super) struct FileInfo {
+    pub(super) size_bytes: Option<u64>,
+    pub(super) inode: Option<u64>,
+    pub(super) device: Option<u64>,
+}
+
+impl FileInfo {
+    pub(super) fn read(old_path: &Path, options: &PreparedOptions) -> Self {
+        let mut info = FileInfo::default();
+        if options.options.include_size {
+            info.size_bytes = fs::metadata(old_path).ok().map(|m| m.len());
+        }
+        // The entry itself, not a symlink's target: that's what gets renamed
+        #[cfg(unix)]
+        if options.options.include_inode {
+            use std::os::unix::fs::MetadataExt;
+            if let Ok(meta) = fs::symlink_metadata(old_path) {
+                info.inode = Some(meta.ino());
+                info.device = Some(meta.dev());
+            }
+        }
+        info
+    }
+
+    pub(super) fn apply_to(self, res: &mut RenameResult) {
+        res.size_bytes = self.size_bytes;
+        res.inode = self.inode;
+        res.device = self.device;
+    }
+}
+
+/// Apply `cmd` to the file at `old_path`; `path` is echoed back in the result.
+/// `index` is the file's position in its batch (0 for single renames).
+pub(super) fn rename_path(
+    path: String,
+    old_path: &Path,
+    cmd: &PreparedCommand,
+    index: usize,
+    options: &PreparedOptions,
+) -> RenameResult {
+    let info = FileInfo::read(old_path, options);
+    let ranges = reserve_counters(&[(cmd, index)]);
+    let mut res = match plan_rename(old_path, cmd, index, options) {
+        Ok(plan) => execute_rename(path, plan, options),
+        Err(e) => RenameResult::from_error(path, e),
+    };
+    info.apply_to(&mut res);
+    commit_counters(&[(cmd, index)], std::slice::from_mut(&mut res), ranges);
+    res
+}
+
+/// Compute the new name for the file at `old_path` without touching the disk.
+pub(super) fn plan_rename<'a>(
+    old_path: &'a Path,
+    cmd: &PreparedCommand,
+    index: usize,
+    options: &PreparedOptions,
+) -> Result<PlannedRename<'a>, NameError> {
+    let fail = |code, message: &str| NameError {
+        code,
+        message: message.to_string(),
+        trim_overflow: None,
+    };
+
+    // One stat answers existence, the precondition and the directory check
+    let Ok(meta) = fs::metadata(old_path) else {
+        return Err(fail(ResultCode::NotFound, "File not found"));
+    };
+
+    let parent = old_path
+        .parent()
+        .ok_or_else(|| fail(ResultCode::InvalidPath, "Invalid path"))?;
+
+    let old_name = old_path
+        .file_name()
+        .ok_or_else(|| fail(ResultCode::InvalidFilename, "Invalid filename"))?;
+
+    if let Some(filter) = &options.name_filter {
+        if !filter.is_match(&old_name.to_string_lossy()) {
+            return Err(fail(ResultCode::FilteredOut, "Filtered out"));
+        }
+    }
+
+    if let Some(precondition) = &options.options.precondition {
+        if !precondition.holds(&meta) {
+            return Err(fail(ResultCode::PreconditionFailed, "Precondition not met"));
+        }
+    }
+
+    // Directories rarely have extensions: `My.Folder` is one stem, not
+    // `My` + `.Folder`
+    let (name_stem, ext) = if meta.is_dir() {
+        (old_name, None)
+    } else {
+        (
+            old_path.file_stem().unwrap_or(old_name),
+            old_path.extension(),
+        )
+    };
+
+    let new_name = match (old_name.to_str(), name_stem.to_str()) {
+        (Some(old_name), Some(name_stem)) => {
+            let ext = ext.and_then(|e| e.to_str()).unwrap_or("");
+            compute_new_name(old_path, old_name, name_stem, ext, cmd, index).map(OsString::from)
+        }
+        _ => compute_new_name_os(old_name, name_stem, ext, cmd.cmd, index),
+    }?;
+
+    let subdir = match cmd.cmd {
+        RenameCommand::SortInto { folder, .. } => Some(sort_folder(
+            cmd.regex()?,
+            folder,
+            &old_name.to_string_lossy(),
+        )?),
+        _ => None,
+    };
+
+    Ok(PlannedRename {
+        old_path,
+        parent,
+        old_name,
+        new_name,
+        subdir,
+    })
+}
+
+pub fn handle_rename(
+    path: String,
+    cmd: RenameCommand,
+    options: Option<RenameOptions>,
+) -> RenameResult {
+    execute(&path, &cmd, &options.unwrap_or_default())
+}
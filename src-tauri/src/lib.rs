@@ -1,322 +1,155 @@
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::Path;
-use regex::Regex;
-
-// --- Enum types for type-safe deserialization ---
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum Position {
-    Start,
-    End,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum CaseMode {
-    Upper,
-    Lower,
-}
+#[cfg(feature = "tauri")]
+mod commands;
+mod engine;
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum WidthMode {
-    Zenkaku,
-    Hankaku,
-}
-
-// --- Rename commands ---
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "mode", content = "config")]
-pub enum RenameCommand {
-    Fixed {
-        name: String,
-        keep_ext: bool,
-    },
-    Serial {
-        prefix: String,
-        suffix: String,
-        number: u32,
-        pad: usize,
-        keep_ext: bool,
-        keep_original: bool,
-    },
-    Replace {
-        from: String,
-        to: String,
-        use_regex: bool,
-    },
-    Add {
-        text: String,
-        position: Position,
-    },
-    Trim {
-        count: usize,
-        position: Position,
-    },
-    Extension {
-        new_ext: String,
-    },
-    Case {
-        mode: CaseMode,
-    },
-    Convert {
-        mode: WidthMode,
-    },
-}
+pub use engine::*;
 
-#[derive(Serialize, Deserialize)]
-pub struct RenameResult {
-    path: String,
-    status: String,
-    new_name: Option<String>,
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+#[cfg(feature = "tauri")]
+use tauri::Manager;
+
+// --- Automation mode ---
+
+/// A `--job` description: the JSON form of a `handle_rename_batch` call.
+#[derive(Deserialize)]
+struct Job {
+    paths: Vec<String>,
+    command: RenameCommand,
+    #[serde(default)]
+    options: RenameOptions,
 }
 
-// --- Character width conversion helpers ---
-
-fn to_zenkaku(s: &str) -> String {
-    s.chars()
-        .map(|c| match c {
-            ' ' => '\u{3000}',
-            '!'..='~' => char::from_u32(c as u32 + 0xFEE0).unwrap_or(c),
-            _ => c,
-        })
-        .collect()
+/// Run one job, keeping the `Counter` store in `counter_dir` if given.
+fn run_job(input: &str, counter_dir: Option<PathBuf>) -> Result<Vec<RenameResult>, String> {
+    let job: Job = serde_json::from_str(input).map_err(|e| format!("Invalid job: {}", e))?;
+    if let Some(dir) = counter_dir {
+        engine::set_counter_dir(dir);
+    }
+    Ok(engine::rename_all(job.paths, &job.command, &job.options))
 }
 
-fn to_hankaku(s: &str) -> String {
-    s.chars()
-        .map(|c| match c {
-            '\u{3000}' => ' ',
-            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
-            _ => c,
-        })
-        .collect()
+/// Bundle identifier from `tauri.conf.json`; names the app data dir.
+const APP_IDENTIFIER: &str = "com.takaki2.ddrenamer";
+
+/// The app data dir as Tauri resolves it, found without starting Tauri, so
+/// `--job` runs count from the same `Counter` store as the app.
+fn app_data_dir() -> Option<PathBuf> {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|h| h.join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| home().map(|h| h.join(".local/share")))
+    };
+    Some(base?.join(APP_IDENTIFIER))
 }
 
-/// Reconstruct filename from stem and extension.
-/// If ext is empty, returns just the stem.
-fn join_name_ext(stem: &str, ext: &str) -> String {
-    if ext.is_empty() {
-        stem.to_string()
+/// 0 when every file was renamed or already had its target name, else 1.
+fn job_exit_code(results: &[RenameResult]) -> i32 {
+    let ok = results
+        .iter()
+        .all(|r| matches!(r.code(), ResultCode::Success | ResultCode::Unchanged));
+    if ok {
+        0
     } else {
-        format!("{}.{}", stem, ext)
+        1
     }
 }
 
-// --- Core rename logic ---
-
-#[tauri::command]
-fn handle_rename(path: String, cmd: RenameCommand) -> RenameResult {
-    let old_path = Path::new(&path);
-    if !old_path.exists() {
-        return RenameResult {
-            path,
-            status: "File not found".into(),
-            new_name: None,
-        };
+/// `--job <file>` (or `--job -` for stdin) runs one job without the GUI
+/// and prints the `RenameResult` array as JSON on stdout. Exits 2 when the
+/// job can't be read. `Counter` uses the app's store in the app data dir.
+/// Returns `None` to start the GUI as usual.
+fn run_cli() -> Option<i32> {
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() != Some("--job") {
+        return None;
     }
-
-    let parent = match old_path.parent() {
-        Some(p) => p,
-        None => {
-            return RenameResult {
-                path,
-                status: "Invalid path".into(),
-                new_name: None,
-            }
-        }
+    let source = args.next().unwrap_or_else(|| "-".to_string());
+    let input = if source == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(&source)
     };
-
-    let old_name = match old_path.file_name().and_then(|n| n.to_str()) {
-        Some(n) => n,
-        None => {
-            return RenameResult {
-                path,
-                status: "Invalid filename".into(),
-                new_name: None,
-            }
+    let results = match input
+        .map_err(|e| e.to_string())
+        .and_then(|i| run_job(&i, app_data_dir()))
+    {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Some(2);
         }
     };
-
-    let ext = old_path
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("");
-    let name_stem = old_path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or(old_name);
-
-    let new_name_res: Result<String, String> = match &cmd {
-        // --- Fixed: replace entire name ---
-        RenameCommand::Fixed { name, keep_ext } => {
-            if *keep_ext && !ext.is_empty() {
-                Ok(join_name_ext(name, ext))
-            } else {
-                Ok(name.clone())
-            }
+    match serde_json::to_string(&results) {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Some(2);
         }
-
-        // --- Serial: prefix + (original?) + number + suffix ---
-        RenameCommand::Serial {
-            prefix,
-            suffix,
-            number,
-            pad,
-            keep_ext,
-            keep_original,
-        } => {
-            let num_str = format!("{:0width$}", number, width = pad);
-            let generated = if *keep_original {
-                // prefix + original_stem + number + suffix
-                format!("{}{}{}{}", prefix, name_stem, num_str, suffix)
-            } else {
-                format!("{}{}{}", prefix, num_str, suffix)
-            };
-
-            if *keep_ext && !ext.is_empty() {
-                Ok(join_name_ext(&generated, ext))
-            } else {
-                Ok(generated)
-            }
-        }
-
-        // --- Replace: string or regex replacement ---
-        RenameCommand::Replace {
-            from,
-            to,
-            use_regex,
-        } => {
-            if *use_regex {
-                match Regex::new(from) {
-                    Ok(re) => Ok(re.replace_all(old_name, to.as_str()).to_string()),
-                    Err(e) => Err(format!("Regex error: {}", e)),
-                }
-            } else {
-                Ok(old_name.replace(from, to))
-            }
-        }
-
-        // --- Add: prepend or append text to stem ---
-        RenameCommand::Add { text, position } => {
-            let new_stem = match position {
-                Position::Start => format!("{}{}", text, name_stem),
-                Position::End => format!("{}{}", name_stem, text),
-            };
-            Ok(join_name_ext(&new_stem, ext))
-        }
-
-        // --- Trim: remove characters from stem ---
-        RenameCommand::Trim { count, position } => {
-            let chars: Vec<char> = name_stem.chars().collect();
-            let len = chars.len();
-
-            if *count >= len {
-                return RenameResult {
-                    path,
-                    status: format!(
-                        "Trim count ({}) exceeds name length ({})",
-                        count, len
-                    ),
-                    new_name: None,
-                };
-            }
-
-            let trimmed: String = match position {
-                Position::Start => chars[*count..].iter().collect(),
-                Position::End => chars[..len - *count].iter().collect(),
-            };
-
-            if trimmed.is_empty() {
-                return RenameResult {
-                    path,
-                    status: "Resulting name is empty after trim".into(),
-                    new_name: None,
-                };
-            }
-
-            Ok(join_name_ext(&trimmed, ext))
-        }
-
-        // --- Extension: change file extension ---
-        RenameCommand::Extension { new_ext } => {
-            let clean_ext = new_ext.trim_start_matches('.');
-            Ok(format!("{}.{}", name_stem, clean_ext))
-        }
-
-        // --- Case: upper/lower conversion (stem only, preserve extension) ---
-        RenameCommand::Case { mode } => {
-            let new_stem = match mode {
-                CaseMode::Upper => name_stem.to_uppercase(),
-                CaseMode::Lower => name_stem.to_lowercase(),
-            };
-            Ok(join_name_ext(&new_stem, ext))
-        }
-
-        // --- Convert: zenkaku/hankaku conversion (stem only, preserve extension) ---
-        RenameCommand::Convert { mode } => {
-            let new_stem = match mode {
-                WidthMode::Zenkaku => to_zenkaku(name_stem),
-                WidthMode::Hankaku => to_hankaku(name_stem),
-            };
-            Ok(join_name_ext(&new_stem, ext))
-        }
-    };
-
-    // --- Execute rename ---
-    match new_name_res {
-        Ok(new_name) => {
-            if new_name.is_empty() {
-                return RenameResult {
-                    path,
-                    status: "Resulting name is empty".into(),
-                    new_name: None,
-                };
-            }
-
-            let new_path = parent.join(&new_name);
-
-            // Prevent overwriting existing files
-            if new_path.exists() {
-                return RenameResult {
-                    path,
-                    status: format!("Target exists: {}", new_name),
-                    new_name: None,
-                };
-            }
-
-            match fs::rename(old_path, &new_path) {
-                Ok(_) => RenameResult {
-                    path,
-                    status: "Success".into(),
-                    new_name: Some(new_name),
-                },
-                Err(e) => RenameResult {
-                    path,
-                    status: e.to_string(),
-                    new_name: None,
-                },
-            }
-        }
-        Err(e) => RenameResult {
-            path,
-            status: e,
-            new_name: None,
-        },
     }
+    Some(job_exit_code(&results))
+}
+
+/// Headless build: only `--job` is available.
+#[cfg(not(feature = "tauri"))]
+pub fn run() {
+    let code = run_cli().unwrap_or_else(|| {
+        eprintln!("usage: --job <file|->");
+        2
+    });
+    std::process::exit(code);
 }
 
+#[cfg(feature = "tauri")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    if let Some(code) = run_cli() {
+        std::process::exit(code);
+    }
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![handle_rename])
+        .invoke_handler(tauri::generate_handler![
+            commands::handle_rename,
+            commands::handle_rename_bytes,
+            commands::handle_rename_batch,
+            commands::retry_failed,
+            commands::handle_rename_recursive,
+            commands::handle_rename_mixed,
+            commands::apply_names,
+            commands::preview_many,
+            commands::count_matches,
+            commands::apply_name_list,
+            commands::analyze_batch,
+            commands::list_with_preview,
+            commands::count_changes,
+            commands::preview_grouped,
+            commands::preview_by_risk,
+            commands::export_plan,
+            commands::validate_name,
+            commands::convert_width,
+            commands::restore_from_journal,
+            commands::restore_from_sidecars,
+            commands::undo_last_batch,
+            commands::undo_ops,
+            commands::reset_counter
+        ])
+        .setup(|app| {
+            engine::set_counter_dir(app.path().app_data_dir()?);
+            Ok(())
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,119 +157,22 @@ mod tests {
     use tempfile::tempdir;
 
     #[test]
-    fn test_rename_fixed() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("test.txt");
-        File::create(&file_path).unwrap();
-
-        let cmd = RenameCommand::Fixed {
-            name: "new_name".into(),
-            keep_ext: true,
-        };
-        let res = handle_rename(file_path.to_str().unwrap().into(), cmd);
-
-        assert_eq!(res.status, "Success");
-        assert_eq!(res.new_name.unwrap(), "new_name.txt");
-    }
-
-    #[test]
-    fn test_rename_serial_suffix() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("test.txt");
-        File::create(&file_path).unwrap();
-
-        let cmd = RenameCommand::Serial {
-            prefix: "".into(),
-            suffix: "_suffix".into(),
-            number: 1,
-            pad: 3,
-            keep_ext: true,
-            keep_original: false,
-        };
-        let res = handle_rename(file_path.to_str().unwrap().into(), cmd);
-
-        assert_eq!(res.status, "Success");
-        assert_eq!(res.new_name.unwrap(), "001_suffix.txt");
-    }
-
-    #[test]
-    fn test_rename_trim_end() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("abcde.txt");
-        File::create(&file_path).unwrap();
-
-        let cmd = RenameCommand::Trim {
-            count: 2,
-            position: Position::End,
-        };
-        let res = handle_rename(file_path.to_str().unwrap().into(), cmd);
-
-        assert_eq!(res.status, "Success");
-        assert_eq!(res.new_name.unwrap(), "abc.txt");
-    }
-
-    #[test]
-    fn test_rename_replace() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("old_name_v1.txt");
-        File::create(&file_path).unwrap();
-
-        let cmd = RenameCommand::Replace {
-            from: "v1".into(),
-            to: "v2".into(),
-            use_regex: false,
-        };
-        let res = handle_rename(file_path.to_str().unwrap().into(), cmd);
-
-        assert_eq!(res.status, "Success");
-        assert_eq!(res.new_name.unwrap(), "old_name_v2.txt");
-    }
-
-    #[test]
-    fn test_rename_replace_regex() {
+    fn test_run_job() {
         let dir = tempdir().unwrap();
-        let file_path = dir.path().join("image_123_test.png");
-        File::create(&file_path).unwrap();
-
-        let cmd = RenameCommand::Replace {
-            from: r"(\d+)".into(),
-            to: "NUM".into(),
-            use_regex: true,
-        };
-        let res = handle_rename(file_path.to_str().unwrap().into(), cmd);
-
-        assert_eq!(res.status, "Success");
-        assert_eq!(res.new_name.unwrap(), "image_NUM_test.png");
-    }
-
-    #[test]
-    fn test_rename_add_start() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("file.txt");
-        File::create(&file_path).unwrap();
-
-        let cmd = RenameCommand::Add {
-            text: "prefix_".into(),
-            position: Position::Start,
-        };
-        let res = handle_rename(file_path.to_str().unwrap().into(), cmd);
-
-        assert_eq!(res.status, "Success");
-        assert_eq!(res.new_name.unwrap(), "prefix_file.txt");
-    }
-
-    #[test]
-    fn test_rename_extension() {
-        let dir = tempdir().unwrap();
-        let file_path = dir.path().join("photo.jpg");
-        File::create(&file_path).unwrap();
-
-        let cmd = RenameCommand::Extension {
-            new_ext: "png".into(),
-        };
-        let res = handle_rename(file_path.to_str().unwrap().into(), cmd);
-
-        assert_eq!(res.status, "Success");
-        assert_eq!(res.new_name.unwrap(), "photo.png");
+        let a = dir.path().join("a.txt");
+        File::create(&a).unwrap();
+        let missing = dir.path().join("missing.txt");
+        let job = serde_json::json!({
+            "paths": [a.to_str().unwrap(), missing.to_str().unwrap()],
+            "command": {"mode": "Add", "config": {"text": "x_", "position": "start"}},
+        });
+
+        let results = run_job(&job.to_string(), None).unwrap();
+
+        assert_eq!(results[0].new_name(), Some("x_a.txt"));
+        assert_eq!(results[1].code(), ResultCode::NotFound);
+        assert_eq!(job_exit_code(&results), 1);
+        assert_eq!(job_exit_code(&results[..1]), 0);
+        assert!(run_job("{\"paths\": []}", None).is_err());
     }
 }